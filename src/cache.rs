@@ -0,0 +1,227 @@
+use crate::error::{BranchWeightError, Result};
+use crate::git::{LinkedObject, ObjectKind, Oid, UnmergedObjects};
+use rustc_hash::FxHashSet;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const VERSION: u32 = 2;
+const STALE_AFTER: Duration = Duration::from_secs(7 * 86400);
+
+// Unmerged objects per branch tip from earlier runs. An entry only holds for
+// the exact base tips it was computed against, so those are part of its file
+// name (`<tip>-<hash of base tips>.json`) and stored inside it as well.
+pub struct BranchCache {
+    dir: PathBuf,
+    base_tips: Vec<String>,
+    key: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Entry {
+    version: u32,
+    base_tips: Vec<String>,
+    tip: String,
+    delta_info: bool,
+    objects: Vec<Row>,
+}
+
+// oid, type, disk size, raw size, delta base, blob path
+#[derive(Serialize, Deserialize)]
+struct Row(String, String, u64, u64, Option<String>, Option<String>);
+
+impl BranchCache {
+    pub fn open(dir: &Path, mut base_tips: Vec<String>) -> Result<Self> {
+        fs::create_dir_all(dir).map_err(|source| BranchWeightError::OutputNotWritable {
+            path: dir.to_path_buf(),
+            source,
+        })?;
+        base_tips.sort();
+        base_tips.dedup();
+        let key = format!("{:016x}", fnv1a(base_tips.join("\n").as_bytes()));
+        Ok(BranchCache { dir: dir.to_path_buf(), base_tips, key })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn path(&self, tip: &str) -> PathBuf {
+        self.dir.join(format!("{}-{}.json", tip, self.key))
+    }
+
+    // Anything unreadable or computed differently is a miss; the branch is
+    // simply scanned again.
+    pub fn load(&self, tip: &str, delta_info: bool) -> Option<UnmergedObjects> {
+        let file = File::open(self.path(tip)).ok()?;
+        let entry: Entry = serde_json::from_reader(BufReader::new(file)).ok()?;
+        if entry.version != VERSION || entry.base_tips != self.base_tips || entry.tip != tip {
+            return None;
+        }
+        if delta_info && !entry.delta_info {
+            return None;
+        }
+
+        let linked = entry
+            .objects
+            .into_iter()
+            .map(|Row(oid, kind, size, raw_size, delta_base, path)| {
+                Some(LinkedObject {
+                    oid: Oid::from_hex(&oid)?,
+                    kind: ObjectKind::parse(&kind)?,
+                    size,
+                    raw_size,
+                    delta_base: match delta_base {
+                        Some(base) => Some(Oid::from_hex(&base)?),
+                        None => None,
+                    },
+                    children: Vec::new(),
+                    path,
+                })
+            })
+            .collect::<Option<_>>()?;
+        Some(UnmergedObjects::link(linked))
+    }
+
+    // Stores the objects at `nodes` of a walk's table.
+    pub fn store(
+        &self,
+        tip: &str,
+        delta_info: bool,
+        objects: &UnmergedObjects,
+        nodes: impl Iterator<Item = usize>,
+    ) -> Result<()> {
+        let entry = Entry {
+            version: VERSION,
+            base_tips: self.base_tips.clone(),
+            tip: tip.to_string(),
+            delta_info,
+            objects: nodes
+                .map(|node| {
+                    let o = &objects[node];
+                    let delta_base = objects.delta_base(node).map(|b| b.to_string());
+                    let path = objects.path(node).map(str::to_string);
+                    Row(o.oid.to_string(), o.kind.as_str().to_string(), o.size, o.raw_size, delta_base, path)
+                })
+                .collect(),
+        };
+
+        // written aside and renamed so a crash never leaves half an entry
+        let path = self.path(tip);
+        let partial = path.with_extension("json.tmp");
+        let write = || -> std::io::Result<()> {
+            let mut writer = BufWriter::new(File::create(&partial)?);
+            serde_json::to_writer(&mut writer, &entry)?;
+            writer.flush()?;
+            fs::rename(&partial, &path)
+        };
+        write().map_err(|source| BranchWeightError::OutputNotWritable { path, source })
+    }
+
+    // Drops entries for tips no ref points at anymore. Entries for other base
+    // tips still serve runs with another `--base`, but once the bases moved
+    // they are never hit again, so they only stay for `STALE_AFTER`.
+    pub fn prune(&self, live_tips: &FxHashSet<&str>) {
+        let Ok(dir) = fs::read_dir(&self.dir) else { return };
+        for file in dir.flatten() {
+            let name = file.file_name();
+            let Some((tip, key)) = name.to_str().and_then(parse_entry_name) else { continue };
+            let stale = || {
+                let age = file.metadata().and_then(|m| m.modified()).map(|modified| modified.elapsed());
+                !matches!(age, Ok(Ok(age)) if age < STALE_AFTER)
+            };
+            if !live_tips.contains(tip) || (key != self.key && stale()) {
+                let _ = fs::remove_file(file.path());
+            }
+        }
+    }
+}
+
+fn parse_entry_name(name: &str) -> Option<(&str, &str)> {
+    let stem = name.strip_suffix(".json").or_else(|| name.strip_suffix(".json.tmp"))?;
+    let (tip, key) = stem.split_once('-')?;
+    let hex = |s: &str| s.bytes().all(|b| b.is_ascii_hexdigit());
+    (Oid::from_hex(tip).is_some() && key.len() == 16 && hex(key)).then_some((tip, key))
+}
+
+// Stable across builds and platforms, unlike the std hashers.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &b| (hash ^ u64::from(b)).wrapping_mul(0x100000001b3))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("branch-weight-cache-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn blob(hex: &str, size: u64, base: Option<&str>) -> LinkedObject {
+        LinkedObject {
+            oid: Oid::from_hex(hex).unwrap(),
+            kind: ObjectKind::Blob,
+            size,
+            raw_size: size * 3,
+            delta_base: base.map(|b| Oid::from_hex(b).unwrap()),
+            children: Vec::new(),
+            path: Some(format!("assets/{}.bin", &hex[..4])),
+        }
+    }
+
+    #[test]
+    fn test_roundtrip_and_invalidation() {
+        let dir = temp_dir("roundtrip");
+        let tip = "1".repeat(40);
+        let base = "2".repeat(40);
+        // c is a delta against an object outside the entry
+        let objects = UnmergedObjects::link(vec![
+            blob(&"a".repeat(40), 10, None),
+            blob(&"b".repeat(40), 20, Some(&"a".repeat(40))),
+            blob(&"c".repeat(40), 30, Some(&"9".repeat(40))),
+        ]);
+
+        let cache = BranchCache::open(&dir, vec![base.clone()]).unwrap();
+        assert!(cache.load(&tip, false).is_none());
+        cache.store(&tip, true, &objects, 0..objects.len()).unwrap();
+
+        let loaded = cache.load(&tip, true).unwrap();
+        assert_eq!(loaded.len(), 3);
+        assert_eq!(loaded[1].oid, objects[1].oid);
+        assert_eq!(loaded[1].raw_size, 60);
+        assert_eq!(loaded[1].delta_base, Some(0));
+        assert_eq!(loaded.delta_base(2), Oid::from_hex(&"9".repeat(40)));
+        assert_eq!(loaded.path(1), Some("assets/bbbb.bin"));
+
+        // without delta info the entry is not good enough for --delta-info
+        let other_tip = "3".repeat(40);
+        cache.store(&other_tip, false, &objects, 0..objects.len()).unwrap();
+        assert!(cache.load(&other_tip, false).is_some());
+        assert!(cache.load(&other_tip, true).is_none());
+
+        // a moved base invalidates everything
+        let moved = BranchCache::open(&dir, vec!["4".repeat(40)]).unwrap();
+        assert!(moved.load(&tip, false).is_none());
+
+        // other bases keep their entries for live tips until they go stale
+        fs::write(dir.join("notes.txt"), "keep").unwrap();
+        moved.prune(&FxHashSet::from_iter([tip.as_str()]));
+        assert!(cache.load(&tip, false).is_some());
+        assert!(cache.load(&other_tip, false).is_none());
+        assert!(dir.join("notes.txt").exists());
+
+        let old = std::time::SystemTime::now() - STALE_AFTER * 2;
+        File::options().write(true).open(cache.path(&tip)).unwrap().set_modified(old).unwrap();
+        cache.prune(&FxHashSet::from_iter([tip.as_str()]));
+        assert!(cache.load(&tip, false).is_some());
+        moved.prune(&FxHashSet::from_iter([tip.as_str()]));
+        assert!(cache.load(&tip, false).is_none());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
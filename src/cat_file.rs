@@ -0,0 +1,350 @@
+use crate::error::{BranchWeightError, Result};
+use crate::git::{capture_stderr, check_status, ObjectKind, Oid};
+use anyhow::Context;
+use rayon::prelude::*;
+use rustc_hash::FxHashMap;
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread::JoinHandle;
+
+// Requests written before reading answers back. A chunk of ids always fits in
+// the pipe buffer, so writing never blocks on git still writing its answers.
+const CHUNK: usize = 256;
+
+const CHECK_FORMAT: &str = "--batch-check=%(objectname) %(objecttype) %(objectsize:disk) %(objectsize) %(deltabase)";
+
+// What `--batch-check` says about an object that exists.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ObjectHeader {
+    pub kind: ObjectKind,
+    pub size: u64,
+    pub raw_size: u64,
+    // None for objects stored whole
+    pub delta_base: Option<Oid>,
+}
+
+// Long-lived `git cat-file --batch-check` and `--batch` processes, one of each
+// per rayon worker plus one for callers outside the pool, started on first
+// use. Headers are memoized, so each object is looked up once per run
+// however many walks and commits touch it.
+pub struct CatFilePool {
+    repo: PathBuf,
+    checks: Vec<Mutex<Option<CatFile>>>,
+    reads: Vec<Mutex<Option<CatFile>>>,
+    // None for missing objects
+    known: Mutex<FxHashMap<Oid, Option<ObjectHeader>>>,
+    // ids sent to batch-check so far
+    checked: AtomicUsize,
+}
+
+struct CatFile {
+    repo: PathBuf,
+    child: Child,
+    stdin: Option<ChildStdin>,
+    stdout: BufReader<ChildStdout>,
+    stderr: Option<JoinHandle<Vec<u8>>>,
+}
+
+impl CatFilePool {
+    pub fn new(repo: &Path) -> Self {
+        let slots = || (0..=rayon::current_num_threads()).map(|_| Mutex::new(None)).collect();
+        CatFilePool {
+            repo: repo.to_path_buf(),
+            checks: slots(),
+            reads: slots(),
+            known: Mutex::new(FxHashMap::default()),
+            checked: AtomicUsize::new(0),
+        }
+    }
+
+    // Headers of `oids` in order, None for missing objects. Only objects not
+    // seen earlier in the run go to batch-check, large lookups spread over the
+    // workers.
+    pub fn headers(&self, oids: &[Oid]) -> Result<Vec<Option<ObjectHeader>>> {
+        let unknown: Vec<Oid> = {
+            let known = self.known.lock().unwrap();
+            oids.iter().filter(|oid| !known.contains_key(oid)).copied().collect()
+        };
+        if unknown.len() <= CHUNK {
+            self.check(&unknown)?;
+        } else {
+            self.known.lock().unwrap().reserve(unknown.len());
+            unknown.par_chunks(CHUNK * 16).try_for_each(|part| self.check(part))?;
+        }
+
+        let known = self.known.lock().unwrap();
+        Ok(oids.iter().map(|oid| known[oid]).collect())
+    }
+
+    // How many object ids have been sent to batch-check in this run.
+    #[cfg(test)]
+    pub fn checked(&self) -> usize {
+        self.checked.load(Ordering::Relaxed)
+    }
+
+    pub fn blob_sizes(&self, oids: &[String]) -> Result<HashMap<String, u64>> {
+        let parsed = oids
+            .iter()
+            .map(|hex| Oid::from_hex(hex).ok_or_else(|| BranchWeightError::unreadable(hex, "not an object id")))
+            .collect::<Result<Vec<Oid>>>()?;
+
+        let mut sizes = HashMap::new();
+        for ((hex, oid), header) in oids.iter().zip(&parsed).zip(self.headers(&parsed)?) {
+            let header = header.ok_or_else(|| BranchWeightError::missing_object(oid))?;
+            if header.kind == ObjectKind::Blob {
+                sizes.insert(hex.clone(), header.size);
+            }
+        }
+        Ok(sizes)
+    }
+
+    fn check(&self, oids: &[Oid]) -> Result<()> {
+        if oids.is_empty() {
+            return Ok(());
+        }
+        let headers = self.with_worker(&self.checks, CHECK_FORMAT, |cat_file| cat_file.check(oids))?;
+        self.checked.fetch_add(oids.len(), Ordering::Relaxed);
+        self.known.lock().unwrap().extend(oids.iter().copied().zip(headers));
+        Ok(())
+    }
+
+    // Streams `oids` through `git cat-file --batch`, handing each object's
+    // type and content to `each`. Objects `wanted` turns down by type and size
+    // are read past without being kept.
+    pub fn read_objects(
+        &self,
+        oids: &[Oid],
+        wanted: impl Fn(&str, u64) -> bool,
+        mut each: impl FnMut(Oid, &str, Vec<u8>),
+    ) -> Result<()> {
+        if oids.is_empty() {
+            return Ok(());
+        }
+        self.with_worker(&self.reads, "--batch", |cat_file| cat_file.read(oids, &wanted, &mut each))
+    }
+
+    fn with_worker<T>(
+        &self,
+        slots: &[Mutex<Option<CatFile>>],
+        format: &str,
+        f: impl FnOnce(&mut CatFile) -> Result<T>,
+    ) -> Result<T> {
+        let slot = rayon::current_thread_index().map_or(slots.len() - 1, |i| i.min(slots.len() - 1));
+        let mut worker = slots[slot].lock().unwrap();
+        if worker.is_none() {
+            *worker = Some(CatFile::spawn(&self.repo, format)?);
+        }
+
+        let result = f(worker.as_mut().unwrap());
+        // a broken pipe or an error halfway through a chunk leaves the process
+        // in an unknown state; the next call starts a fresh one
+        if result.is_err() {
+            *worker = None;
+        }
+        result
+    }
+}
+
+impl CatFile {
+    fn spawn(repo: &Path, format: &str) -> Result<Self> {
+        let mut child = Command::new("git")
+            .args(["cat-file", format])
+            .current_dir(repo)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|err| crate::git::spawn_failed("cat-file", err))?;
+        let stderr = Some(capture_stderr(&mut child));
+        let stdin = child.stdin.take();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        Ok(CatFile { repo: repo.to_path_buf(), child, stdin, stdout, stderr })
+    }
+
+    fn request(&mut self, chunk: &[Oid]) -> Result<()> {
+        let mut request = String::new();
+        for oid in chunk {
+            request.push_str(&oid.to_string());
+            request.push('\n');
+        }
+        let stdin = self.stdin.as_mut().unwrap();
+        match stdin.write_all(request.as_bytes()).and_then(|_| stdin.flush()) {
+            Ok(()) => Ok(()),
+            Err(_) => Err(self.failure()),
+        }
+    }
+
+    // Reads one header line; git exiting midway is reported with its stderr.
+    fn header(&mut self, line: &mut String) -> Result<()> {
+        line.clear();
+        if self.stdout.read_line(line).context("Failed to read git cat-file output")? == 0 {
+            return Err(self.failure());
+        }
+        Ok(())
+    }
+
+    fn check(&mut self, oids: &[Oid]) -> Result<Vec<Option<ObjectHeader>>> {
+        let mut headers = Vec::with_capacity(oids.len());
+        let mut line = String::new();
+
+        for chunk in oids.chunks(CHUNK) {
+            self.request(chunk)?;
+            for _ in chunk {
+                self.header(&mut line)?;
+                headers.push(parse_header(&line)?);
+            }
+        }
+
+        Ok(headers)
+    }
+
+    fn read(
+        &mut self,
+        oids: &[Oid],
+        wanted: &impl Fn(&str, u64) -> bool,
+        each: &mut impl FnMut(Oid, &str, Vec<u8>),
+    ) -> Result<()> {
+        let mut header = String::new();
+
+        for chunk in oids.chunks(CHUNK) {
+            self.request(chunk)?;
+            for _ in chunk {
+                self.header(&mut header)?;
+                let parts: Vec<&str> = header.split_whitespace().collect();
+                if parts.len() == 2 && parts[1] == "missing" {
+                    return Err(BranchWeightError::missing_object(parts[0]));
+                }
+                let [hex, kind, size, ..] = parts.as_slice() else {
+                    return Err(anyhow::anyhow!("Malformed git cat-file header: {}", header.trim()).into());
+                };
+                let size: u64 = size.parse().context("Malformed git cat-file header")?;
+
+                // content plus the newline git adds after it
+                match Oid::from_hex(hex).filter(|_| wanted(kind, size)) {
+                    Some(oid) => {
+                        let mut content = vec![0u8; size as usize + 1];
+                        self.stdout.read_exact(&mut content).map_err(|err| BranchWeightError::unreadable(hex, err))?;
+                        content.truncate(size as usize);
+                        each(oid, kind, content);
+                    }
+                    None => {
+                        io::copy(&mut (&mut self.stdout).take(size + 1), &mut io::sink())
+                            .map_err(|err| BranchWeightError::unreadable(hex, err))?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Why the process stopped answering, from its exit status and stderr.
+    fn failure(&mut self) -> BranchWeightError {
+        drop(self.stdin.take());
+        let status = match self.child.wait() {
+            Ok(status) => status,
+            Err(err) => return anyhow::Error::new(err).context("Failed to wait for git cat-file").into(),
+        };
+        let stderr = self.stderr.take().and_then(|handle| handle.join().ok()).unwrap_or_default();
+        match check_status(&self.repo, "cat-file", status, &stderr) {
+            Err(err) => err,
+            Ok(()) => anyhow::anyhow!("git cat-file exited unexpectedly").into(),
+        }
+    }
+}
+
+fn parse_header(line: &str) -> Result<Option<ObjectHeader>> {
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    if parts.len() == 2 && parts[1] == "missing" {
+        return Ok(None);
+    }
+    let malformed = || anyhow::anyhow!("Malformed git cat-file output: {}", line.trim());
+    let [_, kind, size, raw_size, delta_base] = parts.as_slice() else { return Err(malformed().into()) };
+    Ok(Some(ObjectHeader {
+        kind: ObjectKind::parse(kind).ok_or_else(malformed)?,
+        size: size.parse().map_err(|_| malformed())?,
+        raw_size: raw_size.parse().map_err(|_| malformed())?,
+        // `%(deltabase)` is all zeros for objects stored whole
+        delta_base: Oid::from_hex(delta_base).filter(|base| base.as_bytes().iter().any(|&b| b != 0)),
+    }))
+}
+
+impl Drop for CatFile {
+    fn drop(&mut self) {
+        // closing stdin makes git exit
+        drop(self.stdin.take());
+        let _ = self.child.wait();
+        if let Some(handle) = self.stderr.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn git(repo: &Path, args: &[&str]) -> String {
+        let output = Command::new("git").args(args).current_dir(repo).output().unwrap();
+        assert!(output.status.success(), "git {:?} failed", args);
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    #[test]
+    fn test_blob_sizes_across_chunks_and_threads() {
+        let repo = std::env::temp_dir().join(format!("branch-weight-cat-file-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&repo);
+        std::fs::create_dir_all(&repo).unwrap();
+        git(&repo, &["init", "-q"]);
+
+        let paths: Vec<String> = (0..CHUNK + 50)
+            .map(|i| {
+                let name = format!("f{}", i);
+                std::fs::write(repo.join(&name), format!("{}\n", i).repeat(i + 1)).unwrap();
+                name
+            })
+            .collect();
+        let mut args = vec!["hash-object", "-w"];
+        args.extend(paths.iter().map(String::as_str));
+        let oids: Vec<String> = git(&repo, &args).lines().map(str::to_string).collect();
+
+        let pool = CatFilePool::new(&repo);
+        let expected = pool.blob_sizes(&oids).unwrap();
+        assert_eq!(expected.len(), oids.len());
+        assert!(expected.values().all(|&size| size > 0));
+
+        // memoized and consistent from any thread
+        use rayon::prelude::*;
+        let again: Vec<HashMap<String, u64>> = oids.par_chunks(17).map(|chunk| pool.blob_sizes(chunk).unwrap()).collect();
+        for sizes in again {
+            for (oid, size) in sizes {
+                assert_eq!(expected[&oid], size);
+            }
+        }
+
+        let missing = "0123456789012345678901234567890123456789".to_string();
+        assert!(matches!(
+            pool.blob_sizes(&[oids[0].clone(), missing.clone()]),
+            Err(BranchWeightError::ObjectUnreadable { .. })
+        ));
+        assert_eq!(pool.blob_sizes(&oids[..1]).unwrap().len(), 1);
+
+        let first = Oid::from_hex(&oids[0]).unwrap();
+        let headers = pool.headers(&[first, Oid::from_hex(&missing).unwrap()]).unwrap();
+        assert_eq!(headers[0].map(|h| (h.kind, h.raw_size)), Some((ObjectKind::Blob, 2)));
+        assert_eq!(headers[1], None);
+        let mut contents = Vec::new();
+        pool.read_objects(&[first, first], |kind, _| kind == "blob", |_, _, content| contents.push(content)).unwrap();
+        assert_eq!(contents, [b"0\n".to_vec(), b"0\n".to_vec()]);
+
+        // git's own complaint comes through instead of a bare exit
+        let outside = CatFilePool::new(&std::env::temp_dir());
+        assert!(matches!(outside.headers(&[first]), Err(BranchWeightError::NotARepo(_))));
+
+        let _ = std::fs::remove_dir_all(&repo);
+    }
+}
@@ -0,0 +1,63 @@
+use std::io;
+use std::path::PathBuf;
+use thiserror::Error;
+
+pub type Result<T, E = BranchWeightError> = std::result::Result<T, E>;
+
+#[derive(Debug, Error)]
+pub enum BranchWeightError {
+    #[error("{} is not a git repository", .0.display())]
+    NotARepo(PathBuf),
+
+    #[error("Base branch {0} not found. Use --base to specify.")]
+    DefaultBranchNotFound(String),
+
+    #[error("git binary not found in PATH")]
+    GitMissing(#[source] io::Error),
+
+    #[error("Object {oid} is unreadable: {reason}")]
+    ObjectUnreadable { oid: String, reason: String },
+
+    #[error("Cannot write {}", path.display())]
+    OutputNotWritable {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("git {command} failed: {message}")]
+    Git { command: String, message: String },
+
+    #[error("{0} branch error(s) with --strict, see errors.json")]
+    BranchFailures(usize),
+
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+impl BranchWeightError {
+    // Distinct process exit codes; 1 stays the generic failure and 2 is what
+    // clap uses for usage errors.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            BranchWeightError::NotARepo(_) => 3,
+            BranchWeightError::DefaultBranchNotFound(_) => 4,
+            BranchWeightError::GitMissing(_) => 5,
+            BranchWeightError::ObjectUnreadable { .. } => 6,
+            BranchWeightError::OutputNotWritable { .. } => 7,
+            BranchWeightError::BranchFailures(_) => 8,
+            BranchWeightError::Git { .. } | BranchWeightError::Other(_) => 1,
+        }
+    }
+
+    pub fn unreadable(oid: impl ToString, reason: impl ToString) -> Self {
+        BranchWeightError::ObjectUnreadable {
+            oid: oid.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+
+    pub fn missing_object(oid: impl ToString) -> Self {
+        Self::unreadable(oid, "object is missing")
+    }
+}
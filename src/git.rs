@@ -1,205 +1,1149 @@
-use anyhow::{Context, Result};
-use std::collections::HashMap;
-use std::io::{BufRead, BufReader, Write};
-use std::path::Path;
-use std::process::{Command, Stdio};
+use crate::cat_file::CatFilePool;
+use crate::error::{BranchWeightError, Result};
+use crate::refs::short_name;
+use anyhow::Context;
+use rustc_hash::FxHashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Output, Stdio};
+use std::thread::JoinHandle;
 
 pub struct CommitBlobs {
     pub commit: String,
     pub blobs: HashMap<String, u64>,
 }
 
+// Binary object id, 20 bytes for SHA-1 repositories and 32 for SHA-256 ones.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Oid {
+    Sha1([u8; 20]),
+    Sha256([u8; 32]),
+}
+
+impl Oid {
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        match bytes.len() {
+            20 => Some(Oid::Sha1(bytes.try_into().ok()?)),
+            32 => Some(Oid::Sha256(bytes.try_into().ok()?)),
+            _ => None,
+        }
+    }
+
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        if hex.len() != 40 && hex.len() != 64 {
+            return None;
+        }
+        let mut bytes = [0u8; 32];
+        for (i, pair) in hex.as_bytes().chunks(2).enumerate() {
+            let pair = std::str::from_utf8(pair).ok()?;
+            bytes[i] = u8::from_str_radix(pair, 16).ok()?;
+        }
+        Self::from_bytes(&bytes[..hex.len() / 2])
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            Oid::Sha1(bytes) => bytes,
+            Oid::Sha256(bytes) => bytes,
+        }
+    }
+}
+
+impl fmt::Display for Oid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.as_bytes() {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Oid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ObjectKind {
+    Commit,
+    Tree,
+    Blob,
+    Tag,
+}
+
+impl ObjectKind {
+    pub const ALL: [ObjectKind; 4] = [ObjectKind::Commit, ObjectKind::Tree, ObjectKind::Blob, ObjectKind::Tag];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ObjectKind::Commit => "commit",
+            ObjectKind::Tree => "tree",
+            ObjectKind::Blob => "blob",
+            ObjectKind::Tag => "tag",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "commit" => Some(ObjectKind::Commit),
+            "tree" => Some(ObjectKind::Tree),
+            "blob" => Some(ObjectKind::Blob),
+            "tag" => Some(ObjectKind::Tag),
+            _ => None,
+        }
+    }
+}
+
+// An object reachable from some branch tip but not from the default branch.
+// `raw_size` and `delta_base` are only filled in when delta info is requested;
+// the base is an index into the walk's `UnmergedObjects`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UnmergedObject {
+    pub oid: Oid,
+    pub kind: ObjectKind,
+    pub size: u64,
+    pub raw_size: u64,
+    pub delta_base: Option<u32>,
+}
+
+// Everything one walk found, as a single table that edges and delta bases
+// index into: the objects first, then delta bases the walk did not return.
+// An object's children are the objects of the table it points at: parents
+// and root tree for commits, entries (minus gitlinks) for trees. A blob's
+// path is where the walk first met it, as `rev-list --objects` names it.
+// Edges and paths take most of the room, so they can be released as soon as
+// they have been used.
+#[derive(Debug, Default)]
+pub struct UnmergedObjects {
+    objects: Vec<UnmergedObject>,
+    bases: Vec<Oid>,
+    // object i's children are edges[offsets[i]..offsets[i + 1]]; objects
+    // past the end of `offsets` have none
+    offsets: Vec<usize>,
+    edges: Vec<u32>,
+    // only as long as needed to hold the last blob with a path
+    paths: Vec<Option<Box<str>>>,
+}
+
+impl UnmergedObjects {
+    pub fn with_capacity(capacity: usize) -> Self {
+        UnmergedObjects { objects: Vec::with_capacity(capacity), ..UnmergedObjects::default() }
+    }
+
+    // Builds a table from objects naming their children and delta bases by
+    // id. Children outside the list are dropped, bases outside it appended.
+    pub fn link(linked: Vec<LinkedObject>) -> Self {
+        let mut table = UnmergedObjects::with_capacity(linked.len());
+        let mut rest = Vec::with_capacity(linked.len());
+        for object in linked {
+            let LinkedObject { oid, kind, size, raw_size, delta_base, children, path } = object;
+            table.push(UnmergedObject { oid, kind, size, raw_size, delta_base: None }, path);
+            rest.push((delta_base, children));
+        }
+        let index = table.index();
+        for (node, (delta_base, children)) in rest.into_iter().enumerate() {
+            table.set_children(node as u32, children.iter().filter_map(|child| index.get(child).copied()));
+            if let Some(base) = delta_base {
+                let base = index.get(&base).copied().unwrap_or_else(|| table.push_base(base));
+                table.set_delta_base(node as u32, base);
+            }
+        }
+        table
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, UnmergedObject> {
+        self.objects.iter()
+    }
+
+    // Appends an object without children and returns its index.
+    pub fn push(&mut self, object: UnmergedObject, path: Option<String>) -> u32 {
+        let node = self.objects.len() as u32;
+        self.objects.push(object);
+        if let Some(path) = path {
+            self.paths.resize(node as usize, None);
+            self.paths.push(Some(path.into_boxed_str()));
+        }
+        node
+    }
+
+    // Records the children of `node`. Nodes must come in increasing order;
+    // the ones skipped keep none.
+    pub fn set_children(&mut self, node: u32, children: impl IntoIterator<Item = u32>) {
+        debug_assert!(node as usize >= self.offsets.len());
+        self.offsets.resize(node as usize + 1, self.edges.len());
+        self.edges.extend(children);
+    }
+
+    // Adds a delta base the walk did not return and returns its index. Only
+    // once every object is in, since bases come after them.
+    pub fn push_base(&mut self, oid: Oid) -> u32 {
+        self.bases.push(oid);
+        (self.objects.len() + self.bases.len() - 1) as u32
+    }
+
+    pub fn set_delta_base(&mut self, node: u32, base: u32) {
+        self.objects[node as usize].delta_base = Some(base);
+    }
+
+    // Id of an object or delta base.
+    pub fn oid(&self, index: u32) -> Oid {
+        let index = index as usize;
+        match self.objects.get(index) {
+            Some(object) => object.oid,
+            None => self.bases[index - self.objects.len()],
+        }
+    }
+
+    pub fn delta_base(&self, node: usize) -> Option<Oid> {
+        self.objects[node].delta_base.map(|base| self.oid(base))
+    }
+
+    pub fn children(&self, node: usize) -> &[u32] {
+        let Some(&start) = self.offsets.get(node) else { return &[] };
+        let end = self.offsets.get(node + 1).copied().unwrap_or(self.edges.len());
+        &self.edges[start..end]
+    }
+
+    pub fn path(&self, node: usize) -> Option<&str> {
+        self.paths.get(node).and_then(|path| path.as_deref())
+    }
+
+    // Object ids to their index, for lookups by id.
+    pub fn index(&self) -> FxHashMap<Oid, u32> {
+        self.objects.iter().enumerate().map(|(i, o)| (o.oid, i as u32)).collect()
+    }
+
+    pub fn release_edges(&mut self) {
+        self.offsets = Vec::new();
+        self.edges = Vec::new();
+    }
+
+    pub fn release_paths(&mut self) {
+        self.paths = Vec::new();
+    }
+}
+
+impl std::ops::Index<usize> for UnmergedObjects {
+    type Output = UnmergedObject;
+
+    fn index(&self, node: usize) -> &UnmergedObject {
+        &self.objects[node]
+    }
+}
+
+// An unmerged object that names its children and delta base by id, as a
+// cache entry or a hand-built graph does; `UnmergedObjects::link` turns a
+// list of them into a table.
+#[derive(Debug, Clone)]
+pub struct LinkedObject {
+    pub oid: Oid,
+    pub kind: ObjectKind,
+    pub size: u64,
+    pub raw_size: u64,
+    pub delta_base: Option<Oid>,
+    pub children: Vec<Oid>,
+    pub path: Option<String>,
+}
+
+// A commit that added `blob` at `path` or changed `path` to it. `author` is
+// "Name <email>" and `time` the committer timestamp. `generation` is the
+// length of the longest chain of walked commits ending at `commit`, so it is
+// larger than that of every walked ancestor whatever the timestamps say.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlobChange {
+    pub blob: Oid,
+    pub path: String,
+    pub commit: String,
+    pub author: String,
+    pub time: i64,
+    pub generation: u32,
+}
+
+// An unmerged branch as found by `get_branches`: `name` is the refname
+// without its refs/heads/ or refs/remotes/ prefix, `tip` the hex commit id
+// that was scanned. `last_commit` describes the tip, when it could be read.
+// `upstream` is the remote-tracking branch a local branch is configured to
+// follow (`branch.<name>.remote` and `.merge`), whether or not it exists.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BranchRef {
+    pub refname: String,
+    pub name: String,
+    pub tip: String,
+    pub last_commit: Option<CommitInfo>,
+    pub upstream: Option<String>,
+}
+
+// Committer date (unix seconds), author and subject line of a commit.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct CommitInfo {
+    pub date: i64,
+    pub author_name: String,
+    pub author_email: String,
+    pub subject: String,
+}
+
+impl CommitInfo {
+    // Whole days between the commit and `now`; commits dated in the future
+    // are zero days old.
+    pub fn age_days(&self, now: i64) -> u64 {
+        (now - self.date).max(0) as u64 / 86400
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BrokenRef {
+    pub refname: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefKind {
+    Local,
+    Remote,
+}
+
+impl RefKind {
+    pub fn of(refname: &str) -> Self {
+        if refname.starts_with("refs/remotes/") {
+            RefKind::Remote
+        } else {
+            RefKind::Local
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RefKind::Local => "local",
+            RefKind::Remote => "remote",
+        }
+    }
+}
+
+impl BranchRef {
+    pub fn new(refname: String, tip: String) -> Self {
+        let name = short_name(&refname).to_string();
+        BranchRef { refname, name, tip, last_commit: None, upstream: None }
+    }
+}
+
 pub trait GitOps: Send + Sync {
-    fn get_branches(&self, repo: &Path) -> Result<Vec<(String, String)>>;
-    fn get_unmerged_blobs(&self, repo: &Path, branch: &str, exclude: &str) -> Result<HashMap<String, u64>>;
-    fn get_unmerged_commits(&self, repo: &Path, branch: &str, exclude: &str) -> Result<Vec<CommitBlobs>>;
-    fn detect_default_branch(&self, repo: &Path) -> Result<String>;
+    // Full refnames and tips of every branch under refs/heads and refs/remotes.
+    fn list_refs(&self, repo: &Path) -> Result<Vec<(String, String)>>;
+    // Branches whose tip is not reachable from any of `bases`.
+    // Refs that could not be read come back separately instead of vanishing.
+    fn get_branches(&self, repo: &Path, bases: &[String]) -> Result<(Vec<BranchRef>, Vec<BrokenRef>)>;
+    fn get_unmerged_objects(
+        &self,
+        repo: &Path,
+        tips: &[String],
+        exclude: &[String],
+        delta_info: bool,
+    ) -> Result<UnmergedObjects>;
+    fn get_unmerged_commits(&self, repo: &Path, branch: &str, exclude: &[String]) -> Result<Vec<CommitBlobs>>;
+    // Contents of the given blobs that are smaller than `max_size`; larger
+    // ones are left out.
+    fn get_small_blobs(&self, _repo: &Path, oids: &[Oid], max_size: u64) -> Result<Vec<(Oid, Vec<u8>)>>;
+    // Every commit reachable from `tips` but not `exclude` that adds or
+    // changes a path to one of `blobs`. Merge commits are skipped, root
+    // commits count as adding their whole tree, and renames as additions.
+    fn find_blob_changes(&self, repo: &Path, tips: &[String], exclude: &[String], blobs: &[Oid])
+        -> Result<Vec<BlobChange>>;
+    // The commit `rev` (refname, short name or hex id) points at, if any.
+    fn resolve_commit(&self, repo: &Path, rev: &str) -> Result<Option<String>>;
+    fn git_dir(&self, repo: &Path) -> Result<PathBuf>;
+
+    fn rev_exists(&self, repo: &Path, rev: &str) -> Result<bool> {
+        Ok(self.resolve_commit(repo, rev)?.is_some())
+    }
+
+    fn detect_default_branch(&self, repo: &Path) -> Result<String> {
+        for name in ["refs/heads/master", "refs/heads/main"] {
+            if self.rev_exists(repo, name)? {
+                return Ok(name.to_string());
+            }
+        }
+
+        Err(BranchWeightError::DefaultBranchNotFound("master/main".to_string()))
+    }
 }
 
-pub struct RealGit;
+// (refname, tip) pairs plus the refs that could not be read
+type RefList = (Vec<(String, String)>, Vec<BrokenRef>);
 
-impl GitOps for RealGit {
-    fn get_branches(&self, repo: &Path) -> Result<Vec<(String, String)>> {
+pub struct RealGit {
+    cat_file: CatFilePool,
+}
+
+impl RealGit {
+    // Checks up front that git runs and `repo` is a repository, so those two
+    // problems surface as such instead of as a failing branch scan.
+    pub fn open(repo: &Path) -> Result<Self> {
         let output = Command::new("git")
-            .args([
-                "for-each-ref",
-                "--format=%(refname) %(objectname)",
-                "--no-merged=HEAD",
-                "refs/heads",
-                "refs/remotes",
-            ])
+            .args(["rev-parse", "--git-dir"])
             .current_dir(repo)
             .output()
-            .context("Failed to run git for-each-ref")?;
+            .map_err(|err| match err.kind() {
+                io::ErrorKind::NotFound if repo.is_dir() => BranchWeightError::GitMissing(err),
+                _ => BranchWeightError::NotARepo(repo.to_path_buf()),
+            })?;
 
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        let mut branches = Vec::new();
+        if !output.status.success() {
+            return Err(BranchWeightError::NotARepo(repo.to_path_buf()));
+        }
+        Ok(RealGit { cat_file: CatFilePool::new(repo) })
+    }
+}
 
-        for line in stdout.lines() {
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            if parts.len() >= 2 {
-                let refname = parts[0];
-                let oid = parts[1];
+impl GitOps for RealGit {
+    fn list_refs(&self, repo: &Path) -> Result<Vec<(String, String)>> {
+        let (refs, _) = self.for_each_ref(repo)?;
+        Ok(refs)
+    }
 
-                if refname.ends_with("/HEAD") {
-                    continue;
-                }
+    fn get_branches(&self, repo: &Path, bases: &[String]) -> Result<(Vec<BranchRef>, Vec<BrokenRef>)> {
+        // with several --no-merged, git lists refs reachable from none of them
+        let filters: Vec<String> = bases.iter().map(|b| format!("--no-merged={}", b)).collect();
+        let branches = self.unmerged_refs(repo, &filters)?;
 
-                let branch_name = refname
-                    .strip_prefix("refs/heads/")
-                    .or_else(|| refname.strip_prefix("refs/remotes/"))
-                    .unwrap_or(refname)
-                    .to_string();
+        // --no-merged silently drops refs whose tip is missing, so look for
+        // those among all refs
+        let (all, mut broken) = self.for_each_ref(repo)?;
+        let missing = self.missing_objects(all.iter().map(|(_, oid)| oid.as_str()))?;
+        broken.extend(all.into_iter().filter(|(_, oid)| missing.contains(oid)).map(|(refname, oid)| BrokenRef {
+            refname,
+            reason: format!("tip {} is missing", oid),
+        }));
+        broken.sort_by(|a, b| a.refname.cmp(&b.refname));
+
+        Ok((branches, broken))
+    }
 
-                branches.push((branch_name, oid.to_string()));
+    fn get_unmerged_objects(
+        &self,
+        repo: &Path,
+        tips: &[String],
+        exclude: &[String],
+        delta_info: bool,
+    ) -> Result<UnmergedObjects> {
+        let mut rev_list = Command::new("git")
+            .args(["rev-list", "--objects", "--stdin"])
+            .current_dir(repo)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|err| spawn_failed("rev-list", err))?;
+        let rev_errors = capture_stderr(&mut rev_list);
+
+        let revs: Vec<String> = tips
+            .iter()
+            .cloned()
+            .chain(exclude.iter().map(|e| format!("^{}", e)))
+            .collect();
+        let mut rev_stdin = rev_list.stdin.take().unwrap();
+        // write errors mean the process died; its exit status says why
+        let revs_handle = std::thread::spawn(move || {
+            for rev in revs {
+                let _ = writeln!(rev_stdin, "{}", rev);
             }
+        });
+
+        let mut oids = Vec::new();
+        let mut paths = Vec::new();
+        // paths are raw bytes, not necessarily UTF-8
+        for line in BufReader::new(rev_list.stdout.take().unwrap()).split(b'\n') {
+            let line = line.context("Failed to read git rev-list output")?;
+            let line = String::from_utf8_lossy(&line);
+            let (oid, path) = line.split_once(' ').unwrap_or((&line, ""));
+            let Some(oid) = Oid::from_hex(oid) else { continue };
+            oids.push(oid);
+            paths.push((!path.is_empty()).then(|| path.to_string()));
+        }
+        let _ = revs_handle.join();
+        finish(repo, "rev-list", rev_list, rev_errors)?;
+
+        // sizes come from the shared batch-check processes, which always
+        // report the raw size and delta base too and remember them, so
+        // walking the same objects again for `--details` looks nothing up
+        let headers = self.cat_file.headers(&oids)?;
+        let mut objects = UnmergedObjects::with_capacity(oids.len());
+        let mut delta_bases = Vec::new();
+        for ((oid, path), header) in oids.into_iter().zip(paths).zip(headers) {
+            let header = header.ok_or_else(|| BranchWeightError::missing_object(oid))?;
+            let object = UnmergedObject {
+                oid,
+                kind: header.kind,
+                size: header.size,
+                raw_size: if delta_info { header.raw_size } else { 0 },
+                delta_base: None,
+            };
+            let node = objects.push(object, path.filter(|_| header.kind == ObjectKind::Blob));
+            if let Some(base) = header.delta_base.filter(|_| delta_info) {
+                delta_bases.push((node, base));
+            }
+        }
+
+        let index = objects.index();
+        for (node, base) in delta_bases {
+            let base = index.get(&base).copied().unwrap_or_else(|| objects.push_base(base));
+            objects.set_delta_base(node, base);
+        }
+        self.read_children(&mut objects, &index)?;
+
+        Ok(objects)
+    }
+
+    fn resolve_commit(&self, repo: &Path, rev: &str) -> Result<Option<String>> {
+        let output = Command::new("git")
+            .args(["rev-parse", "--verify", "--quiet", &format!("{}^{{commit}}", rev)])
+            .current_dir(repo)
+            .output()
+            .map_err(|err| spawn_failed("rev-parse", err))?;
+
+        if !output.status.success() {
+            return Ok(None);
         }
+        Ok(Some(String::from_utf8_lossy(&output.stdout).trim().to_string()))
+    }
 
-        Ok(branches)
+    fn git_dir(&self, repo: &Path) -> Result<PathBuf> {
+        let output = run_git(repo, &["rev-parse", "--absolute-git-dir"])?;
+        Ok(PathBuf::from(String::from_utf8_lossy(&output.stdout).trim()))
     }
 
-    fn get_unmerged_blobs(&self, repo: &Path, branch: &str, exclude: &str) -> Result<HashMap<String, u64>> {
+    // rev-list piped straight into a single `diff-tree --stdin`, so a branch
+    // costs two processes however many commits it has. As with plain
+    // diff-tree, root and merge commits list no changes.
+    fn get_unmerged_commits(&self, repo: &Path, branch: &str, exclude: &[String]) -> Result<Vec<CommitBlobs>> {
         let mut rev_list = Command::new("git")
-            .args(["rev-list", "--objects", branch, "--not", exclude])
+            .args(["rev-list", branch, "--not"])
+            .args(exclude)
             .current_dir(repo)
             .stdout(Stdio::piped())
-            .stderr(Stdio::null())
+            .stderr(Stdio::piped())
             .spawn()
-            .context("Failed to spawn git rev-list")?;
+            .map_err(|err| spawn_failed("rev-list", err))?;
+        let rev_list_errors = capture_stderr(&mut rev_list);
 
-        let mut cat_file = Command::new("git")
-            .args(["cat-file", "--batch-check=%(objectname) %(objecttype) %(objectsize:disk)"])
+        let mut diff_tree = Command::new("git")
+            .args(["diff-tree", "--stdin", "-r", "--diff-filter=AM"])
+            .current_dir(repo)
+            .stdin(Stdio::from(rev_list.stdout.take().unwrap()))
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|err| spawn_failed("diff-tree", err))?;
+        let diff_tree_errors = capture_stderr(&mut diff_tree);
+
+        let commits = parse_diff_tree(BufReader::new(diff_tree.stdout.take().unwrap()))?;
+
+        // a failing diff-tree takes rev-list down with a broken pipe, so its
+        // own error is the one worth reporting
+        let diffed = finish(repo, "diff-tree", diff_tree, diff_tree_errors);
+        let listed = finish(repo, "rev-list", rev_list, rev_list_errors);
+        diffed.and(listed)?;
+
+        let mut blobs: Vec<String> = commits.iter().flat_map(|(_, blobs)| blobs.iter().cloned()).collect();
+        blobs.sort_unstable();
+        blobs.dedup();
+        let sizes = self.get_blob_sizes(repo, &blobs)?;
+
+        Ok(commits
+            .into_iter()
+            .filter(|(_, blobs)| !blobs.is_empty())
+            .map(|(commit, blobs)| CommitBlobs {
+                commit,
+                blobs: blobs
+                    .into_iter()
+                    .filter_map(|oid| sizes.get(&oid).map(|&size| (oid, size)))
+                    .collect(),
+            })
+            .collect())
+    }
+
+    fn get_small_blobs(&self, _repo: &Path, oids: &[Oid], max_size: u64) -> Result<Vec<(Oid, Vec<u8>)>> {
+        let mut blobs = Vec::new();
+        let wanted = |kind: &str, size: u64| kind == "blob" && size < max_size;
+        self.cat_file.read_objects(oids, wanted, |oid, _, content| blobs.push((oid, content)))?;
+        Ok(blobs)
+    }
+
+    // One `git log --raw` over the whole unmerged history; only the lines
+    // naming a wanted blob are kept. Every commit is listed, even one that
+    // only deletes, so generations run through it.
+    fn find_blob_changes(
+        &self,
+        repo: &Path,
+        tips: &[String],
+        exclude: &[String],
+        blobs: &[Oid],
+    ) -> Result<Vec<BlobChange>> {
+        if blobs.is_empty() || tips.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut log = Command::new("git")
+            .args(["-c", "core.quotePath=false", "log", "--stdin", "--topo-order", "--reverse", "--root"])
+            .args(["--no-renames", "-r", "--raw", "--no-abbrev"])
+            .arg("--format=%x01%H %ct %P%x02%an <%ae>")
             .current_dir(repo)
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
-            .stderr(Stdio::null())
+            .stderr(Stdio::piped())
             .spawn()
-            .context("Failed to spawn git cat-file")?;
-
-        let rev_stdout = rev_list.stdout.take().unwrap();
-        let cat_stdin = cat_file.stdin.take().unwrap();
-        let cat_stdout = cat_file.stdout.take().unwrap();
-
-        let writer_handle = std::thread::spawn(move || {
-            let reader = BufReader::new(rev_stdout);
-            let mut writer = cat_stdin;
-            for line in reader.lines() {
-                if let Ok(line) = line {
-                    if let Some(oid) = line.split_whitespace().next() {
-                        let _ = writeln!(writer, "{}", oid);
-                    }
-                }
+            .map_err(|err| spawn_failed("log", err))?;
+        let log_errors = capture_stderr(&mut log);
+
+        let revs: Vec<String> = tips
+            .iter()
+            .cloned()
+            .chain(exclude.iter().map(|e| format!("^{}", e)))
+            .collect();
+        let mut stdin = log.stdin.take().unwrap();
+        let revs_handle = std::thread::spawn(move || {
+            for rev in revs {
+                let _ = writeln!(stdin, "{}", rev);
             }
         });
 
-        let mut blobs: HashMap<String, u64> = HashMap::new();
-        let reader = BufReader::new(cat_stdout);
+        let wanted: HashSet<Oid> = blobs.iter().copied().collect();
+        let changes = parse_blob_changes(BufReader::new(log.stdout.take().unwrap()), &wanted)?;
+
+        let _ = revs_handle.join();
+        finish(repo, "log", log, log_errors)?;
+        Ok(changes)
+    }
+}
+
+impl RealGit {
+    // Refs under refs/heads and refs/remotes, plus the ones git skipped with
+    // an "ignoring broken ref" style warning.
+    fn for_each_ref(&self, repo: &Path) -> Result<RefList> {
+        let output = run_git(repo, &["for-each-ref", "--format=%(refname) %(objectname)", "refs/heads", "refs/remotes"])?;
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let mut refs = Vec::new();
+
+        for line in stdout.lines() {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            if parts.len() >= 2 {
+                let refname = parts[0];
+                let oid = parts[1];
 
-        for line in reader.lines() {
-            if let Ok(line) = line {
-                let parts: Vec<&str> = line.split_whitespace().collect();
-                if parts.len() >= 3 && parts[1] == "blob" {
-                    if let Ok(size) = parts[2].parse::<u64>() {
-                        blobs.insert(parts[0].to_string(), size);
-                    }
+                if refname.ends_with("/HEAD") {
+                    continue;
                 }
+
+                refs.push((refname.to_string(), oid.to_string()));
             }
         }
 
-        let _ = writer_handle.join();
-        let _ = rev_list.wait();
-        let _ = cat_file.wait();
+        let broken = parse_broken_refs(&String::from_utf8_lossy(&output.stderr));
 
-        Ok(blobs)
+        Ok((refs, broken))
     }
 
-    fn detect_default_branch(&self, repo: &Path) -> Result<String> {
-        for name in ["refs/heads/master", "refs/heads/main"] {
-            let output = Command::new("git")
-                .args(["rev-parse", "--verify", name])
-                .current_dir(repo)
-                .output()?;
+    // The branches left after `filters`, with their tip commit's details.
+    // Only safe with --no-merged filters: those drop refs whose tip is
+    // missing, which would otherwise make git die on the commit atoms.
+    fn unmerged_refs(&self, repo: &Path, filters: &[String]) -> Result<Vec<BranchRef>> {
+        let format = "--format=%(refname)%00%(objectname)%00%(upstream)%00%(committerdate:unix)%00%(authorname)%00%(authoremail)%00%(contents:subject)";
+        let mut args = vec!["for-each-ref", format];
+        args.extend(filters.iter().map(String::as_str));
+        args.extend(["refs/heads", "refs/remotes"]);
+        let output = run_git(repo, &args)?;
+        Ok(parse_unmerged_refs(&output.stdout))
+    }
 
-            if output.status.success() {
-                return Ok(name.to_string());
-            }
-        }
+    // Commits and trees are read in table order, so each one's edges go
+    // straight in. Children outside the table are merged and left out.
+    fn read_children(&self, objects: &mut UnmergedObjects, index: &FxHashMap<Oid, u32>) -> Result<()> {
+        let wanted: Vec<Oid> = objects
+            .iter()
+            .filter(|o| matches!(o.kind, ObjectKind::Commit | ObjectKind::Tree))
+            .map(|o| o.oid)
+            .collect();
 
-        anyhow::bail!("Could not detect default branch (master/main). Use --branch to specify.")
+        self.cat_file.read_objects(&wanted, |_, _| true, |oid, kind, content| {
+            let edges = match kind {
+                "commit" => parse_commit_edges(&content),
+                "tree" => parse_tree_edges(&content, oid.as_bytes().len()),
+                _ => return,
+            };
+            objects.set_children(index[&oid], edges.iter().filter_map(|child| index.get(child).copied()));
+        })
     }
 
-    fn get_unmerged_commits(&self, repo: &Path, branch: &str, exclude: &str) -> Result<Vec<CommitBlobs>> {
-        let commits_output = Command::new("git")
-            .args(["rev-list", branch, "--not", exclude])
-            .current_dir(repo)
-            .output()
-            .context("Failed to run git rev-list")?;
+    fn missing_objects<'a>(&self, oids: impl Iterator<Item = &'a str>) -> Result<HashSet<String>> {
+        let oids: Vec<Oid> = oids.filter_map(Oid::from_hex).collect();
+        let headers = self.cat_file.headers(&oids)?;
+        Ok(oids
+            .iter()
+            .zip(headers)
+            .filter(|(_, header)| header.is_none())
+            .map(|(oid, _)| oid.to_string())
+            .collect())
+    }
 
-        let commit_list: Vec<String> = String::from_utf8_lossy(&commits_output.stdout)
-            .lines()
-            .map(|s| s.to_string())
-            .collect();
+    // Sizes come from the pool opened with the repository, so `repo` only
+    // matters to the other commands.
+    fn get_blob_sizes(&self, _repo: &Path, oids: &[String]) -> Result<HashMap<String, u64>> {
+        self.cat_file.blob_sizes(oids)
+    }
+}
 
-        let mut results: Vec<CommitBlobs> = Vec::new();
+// Runs a git command to completion, failing with its stderr on a non-zero exit.
+fn run_git(repo: &Path, args: &[&str]) -> Result<Output> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(repo)
+        .output()
+        .map_err(|err| spawn_failed(args[0], err))?;
+    check_status(repo, args[0], output.status, &output.stderr)?;
+    Ok(output)
+}
 
-        for commit in commit_list {
-            let diff_output = Command::new("git")
-                .args(["diff-tree", "-r", "--diff-filter=AM", "--no-commit-id", &commit])
-                .current_dir(repo)
-                .output()?;
+pub(crate) fn spawn_failed(command: &str, err: io::Error) -> BranchWeightError {
+    if err.kind() == io::ErrorKind::NotFound {
+        BranchWeightError::GitMissing(err)
+    } else {
+        anyhow::Error::new(err).context(format!("Failed to run git {}", command)).into()
+    }
+}
 
-            let mut blobs: Vec<String> = Vec::new();
-            for line in String::from_utf8_lossy(&diff_output.stdout).lines() {
-                let parts: Vec<&str> = line.split_whitespace().collect();
-                if parts.len() >= 4 {
-                    blobs.push(parts[3].to_string());
-                }
-            }
+pub(crate) fn check_status(repo: &Path, command: &str, status: ExitStatus, stderr: &[u8]) -> Result<()> {
+    if status.success() {
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(stderr);
+    let message = match stderr.trim() {
+        "" => status.to_string(),
+        stderr => stderr.to_string(),
+    };
+    Err(classify_git_error(repo, command, message))
+}
 
-            if !blobs.is_empty() {
-                let sizes = self.get_blob_sizes(repo, &blobs)?;
-                results.push(CommitBlobs { commit, blobs: sizes });
+// Maps git's fatal messages onto the error kinds callers care about.
+fn classify_git_error(repo: &Path, command: &str, message: String) -> BranchWeightError {
+    if message.contains("not a git repository") {
+        return BranchWeightError::NotARepo(repo.to_path_buf());
+    }
+    let about_object = ["missing", "bad object", "unable to read", "corrupt", "invalid object"]
+        .iter()
+        .any(|needle| message.contains(needle));
+    let oid = message
+        .split(|c: char| !c.is_ascii_hexdigit())
+        .find(|word| Oid::from_hex(word).is_some());
+    match oid {
+        Some(oid) if about_object => BranchWeightError::ObjectUnreadable { oid: oid.to_string(), reason: message },
+        _ => BranchWeightError::Git { command: command.to_string(), message },
+    }
+}
+
+// Drains stderr on its own thread so a chatty child cannot block on it while
+// we are still reading its stdout.
+pub(crate) fn capture_stderr(child: &mut Child) -> JoinHandle<Vec<u8>> {
+    let mut stderr = child.stderr.take().unwrap();
+    std::thread::spawn(move || {
+        let mut buf = Vec::new();
+        let _ = stderr.read_to_end(&mut buf);
+        buf
+    })
+}
+
+fn finish(repo: &Path, command: &str, mut child: Child, stderr: JoinHandle<Vec<u8>>) -> Result<()> {
+    let status = child.wait().with_context(|| format!("Failed to wait for git {}", command))?;
+    let stderr = stderr.join().unwrap_or_default();
+    check_status(repo, command, status, &stderr)
+}
+
+// git skips refs it cannot read with warnings like
+// "warning: ignoring broken ref refs/heads/x"; keep them as failures.
+fn parse_broken_refs(stderr: &str) -> Vec<BrokenRef> {
+    stderr
+        .lines()
+        .filter_map(|line| {
+            let message = line.strip_prefix("warning: ")?;
+            if !message.starts_with("ignoring ") {
+                return None;
             }
-        }
+            let (reason, refname) = message.rsplit_once(' ')?;
+            if !(refname.starts_with("refs/heads/") || refname.starts_with("refs/remotes/")) {
+                return None;
+            }
+            Some(BrokenRef {
+                refname: refname.to_string(),
+                reason: reason.to_string(),
+            })
+        })
+        .collect()
+}
 
-        Ok(results)
+// NUL separated refname, tip, upstream, committer date, author name, <email>
+// and subject, one ref per line.
+fn parse_unmerged_refs(stdout: &[u8]) -> Vec<BranchRef> {
+    let mut branches = Vec::new();
+    for line in stdout.split(|&b| b == b'\n') {
+        let line = String::from_utf8_lossy(line);
+        let fields: Vec<&str> = line.split('\0').collect();
+        let [refname, tip, upstream, date, name, email, subject] = fields.as_slice() else { continue };
+        if refname.ends_with("/HEAD") {
+            continue;
+        }
+        let last_commit = date.parse().ok().map(|date| CommitInfo {
+            date,
+            author_name: name.to_string(),
+            author_email: email.trim_start_matches('<').trim_end_matches('>').to_string(),
+            subject: subject.to_string(),
+        });
+        // a branch following another local one has no remote counterpart
+        let upstream = upstream.starts_with("refs/remotes/").then(|| upstream.to_string());
+        branches.push(BranchRef { last_commit, upstream, ..BranchRef::new(refname.to_string(), tip.to_string()) });
     }
+    branches
 }
 
-impl RealGit {
-    fn get_blob_sizes(&self, repo: &Path, oids: &[String]) -> Result<HashMap<String, u64>> {
-        if oids.is_empty() {
-            return Ok(HashMap::new());
+// `diff-tree --stdin` prints each commit id followed by its raw diff lines,
+// ":<old mode> <new mode> <old oid> <new oid> <status>\t<path>". Submodule
+// entries are skipped, their commits live in another repository, and so are
+// mode-only changes, which add no content.
+fn parse_diff_tree(reader: impl BufRead) -> Result<Vec<(String, Vec<String>)>> {
+    let mut commits: Vec<(String, Vec<String>)> = Vec::new();
+    for line in reader.lines() {
+        let line = line.context("Failed to read git diff-tree output")?;
+        let Some(raw) = line.strip_prefix(':') else {
+            if !line.is_empty() {
+                commits.push((line, Vec::new()));
+            }
+            continue;
+        };
+        let fields: Vec<&str> = raw.split('\t').next().unwrap_or_default().split(' ').collect();
+        if let (Some((_, blobs)), [_, mode, old, blob, ..]) = (commits.last_mut(), fields.as_slice()) {
+            if *mode != "160000" && old != blob {
+                blobs.push(blob.to_string());
+            }
         }
+    }
+    Ok(commits)
+}
 
-        let mut cat_file = Command::new("git")
-            .args(["cat-file", "--batch-check=%(objectname) %(objecttype) %(objectsize:disk)"])
-            .current_dir(repo)
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .spawn()
-            .context("Failed to spawn git cat-file")?;
+// `git log --raw` with a "\x01<commit> <time> <parents>\x02<author>" header
+// per commit. Only additions and modifications are kept. Paths git had to
+// quote come back C-style quoted and are unquoted here.
+fn parse_blob_changes(reader: impl BufRead, wanted: &HashSet<Oid>) -> Result<Vec<BlobChange>> {
+    let mut changes = Vec::new();
+    // commits come parents first, so each parent's generation is known
+    let mut generations: HashMap<String, u32> = HashMap::new();
+    let mut header: Option<(String, i64, String, u32)> = None;
+    for line in reader.split(b'\n') {
+        let line = line.context("Failed to read git log output")?;
+        let line = String::from_utf8_lossy(&line);
+        if let Some(rest) = line.strip_prefix('\u{1}') {
+            let (ids, author) = rest.split_once('\u{2}').unwrap_or((rest, ""));
+            let mut parts = ids.split(' ');
+            let commit = parts.next().unwrap_or_default().to_string();
+            let time = parts.next().and_then(|t| t.parse().ok()).unwrap_or(0);
+            let generation = parts.filter_map(|parent| generations.get(parent)).max().map_or(1, |g| g + 1);
+            generations.insert(commit.clone(), generation);
+            header = Some((commit, time, author.to_string(), generation));
+            continue;
+        }
+        let (Some(raw), Some((commit, time, author, generation))) = (line.strip_prefix(':'), &header) else { continue };
+        let Some((fields, path)) = raw.split_once('\t') else { continue };
+        let fields: Vec<&str> = fields.split(' ').collect();
+        let [_, mode, old, blob, status, ..] = fields.as_slice() else { continue };
+        let Some(oid) = Oid::from_hex(blob) else { continue };
+        if !matches!(*status, "A" | "M") || *mode == "160000" || old == blob || !wanted.contains(&oid) {
+            continue;
+        }
+        changes.push(BlobChange {
+            blob: oid,
+            path: unquote_path(path),
+            commit: commit.clone(),
+            author: author.clone(),
+            time: *time,
+            generation: *generation,
+        });
+    }
+    Ok(changes)
+}
 
-        {
-            let stdin = cat_file.stdin.as_mut().unwrap();
-            for oid in oids {
-                writeln!(stdin, "{}", oid)?;
+// Undoes git's C-style quoting of unusual paths: "a\tb", "\"x\"", octal
+// escapes for bytes it will not print as-is.
+fn unquote_path(path: &str) -> String {
+    let Some(inner) = path.strip_prefix('"').and_then(|p| p.strip_suffix('"')) else {
+        return path.to_string();
+    };
+    let mut bytes = Vec::with_capacity(inner.len());
+    let mut rest = inner.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        rest = tail;
+        if b != b'\\' {
+            bytes.push(b);
+            continue;
+        }
+        let Some((&escape, tail)) = rest.split_first() else { break };
+        rest = tail;
+        match escape {
+            b'0'..=b'7' if rest.len() >= 2 => {
+                let digits = [escape, rest[0], rest[1]];
+                rest = &rest[2..];
+                let value = digits.iter().fold(0u32, |acc, d| acc * 8 + (d - b'0') as u32);
+                bytes.push(value as u8);
             }
+            b'a' => bytes.push(0x07),
+            b'b' => bytes.push(0x08),
+            b'f' => bytes.push(0x0c),
+            b'n' => bytes.push(b'\n'),
+            b'r' => bytes.push(b'\r'),
+            b't' => bytes.push(b'\t'),
+            b'v' => bytes.push(0x0b),
+            other => bytes.push(other),
         }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
 
-        let output = cat_file.wait_with_output()?;
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        let mut sizes = HashMap::new();
+fn parse_commit_edges(content: &[u8]) -> Vec<Oid> {
+    let mut edges = Vec::new();
+    for line in content.split(|&b| b == b'\n') {
+        if line.is_empty() {
+            break;
+        }
+        if let Some(hex) = line.strip_prefix(b"tree ").or_else(|| line.strip_prefix(b"parent ")) {
+            if let Some(oid) = std::str::from_utf8(hex).ok().and_then(Oid::from_hex) {
+                edges.push(oid);
+            }
+        }
+    }
+    edges
+}
 
-        for line in stdout.lines() {
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            if parts.len() >= 3 && parts[1] == "blob" {
-                if let Ok(size) = parts[2].parse::<u64>() {
-                    sizes.insert(parts[0].to_string(), size);
-                }
+fn parse_tree_edges(content: &[u8], hash_len: usize) -> Vec<Oid> {
+    let mut edges = Vec::new();
+    let mut rest = content;
+    while let Some(nul) = rest.iter().position(|&b| b == 0) {
+        if rest.len() < nul + 1 + hash_len {
+            break;
+        }
+        let is_gitlink = rest.starts_with(b"160000 ");
+        if !is_gitlink {
+            if let Some(oid) = Oid::from_bytes(&rest[nul + 1..nul + 1 + hash_len]) {
+                edges.push(oid);
             }
         }
+        rest = &rest[nul + 1 + hash_len..];
+    }
+    edges
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_diff_tree() {
+        let c1 = "1".repeat(40);
+        let c2 = "2".repeat(40);
+        let (a, b, s) = ("a".repeat(40), "b".repeat(40), "c".repeat(40));
+        let zero = "0".repeat(40);
+        let output = format!(
+            "{c1}\n:100644 100644 {zero} {a} M\tsrc/with space.rs\n:000000 160000 {zero} {s} A\tvendor/lib\n{c2}\n:000000 100644 {zero} {b} A\tb.bin\n:100644 100755 {a} {a} M\tsrc/with space.rs\n"
+        );
+
+        let commits = parse_diff_tree(output.as_bytes()).unwrap();
+        assert_eq!(commits, vec![(c1, vec![a]), (c2, vec![b])]);
+    }
+
+    #[test]
+    fn test_unmerged_objects_table() {
+        let oid = |b: u8| Oid::Sha1([b; 20]);
+        let object = |b: u8, kind: ObjectKind, base: Option<u8>, children: &[u8]| LinkedObject {
+            oid: oid(b),
+            kind,
+            size: b as u64,
+            raw_size: 0,
+            delta_base: base.map(oid),
+            children: children.iter().map(|&c| oid(c)).collect(),
+            path: (kind == ObjectKind::Blob).then(|| format!("f{}", b)),
+        };
+        // 9 is merged: an edge to it is dropped, as a base it is appended
+        let mut objects = UnmergedObjects::link(vec![
+            object(1, ObjectKind::Commit, None, &[2, 9]),
+            object(3, ObjectKind::Blob, None, &[]),
+            object(2, ObjectKind::Tree, None, &[3, 4]),
+            object(4, ObjectKind::Blob, Some(9), &[]),
+        ]);
+
+        assert_eq!(objects.len(), 4);
+        assert_eq!(objects.children(0), [2]);
+        assert_eq!(objects.children(1), [] as [u32; 0]);
+        assert_eq!(objects.children(2), [1, 3]);
+        assert_eq!(objects.children(3), [] as [u32; 0]);
+        assert_eq!(objects[3].delta_base, Some(4));
+        assert_eq!(objects.delta_base(3), Some(oid(9)));
+        assert_eq!((objects.path(0), objects.path(3)), (None, Some("f4")));
+
+        objects.release_edges();
+        objects.release_paths();
+        assert_eq!(objects.children(2), [] as [u32; 0]);
+        assert_eq!(objects.path(3), None);
+        assert_eq!(objects.delta_base(3), Some(oid(9)));
+    }
+
+    #[test]
+    fn test_parse_blob_changes() {
+        let (c1, mid, c2) = ("1".repeat(40), "5".repeat(40), "2".repeat(40));
+        let (a, b) = ("a".repeat(40), "b".repeat(40));
+        let zero = "0".repeat(40);
+        let base = "9".repeat(40);
+        // c2 is committed before its grandparent c1; mid only deletes
+        let output = format!(
+            "\u{1}{c1} 1700000000 {base}\u{2}Ann <ann@example.com>\n\n:000000 100644 {zero} {a} A\tdump/big file.sql\n:000000 100644 {zero} {b} A\tsmall.txt\n\n\u{1}{mid} 1700000001 {c1}\u{2}Ann <ann@example.com>\n\n:100644 000000 {a} {zero} D\tdump/big file.sql\n\n\u{1}{c2} 1600000000 {mid}\u{2}Bob <bob@example.com>\n\n:100644 100644 {b} {a} M\t\"tab\\there\\303\\251\"\n"
+        );
+        let wanted: HashSet<Oid> = [Oid::from_hex(&a).unwrap()].into_iter().collect();
+
+        let changes = parse_blob_changes(output.as_bytes(), &wanted).unwrap();
+        let summary: Vec<(&str, &str, &str, i64, u32)> = changes
+            .iter()
+            .map(|c| (c.commit.as_str(), c.path.as_str(), c.author.as_str(), c.time, c.generation))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (c1.as_str(), "dump/big file.sql", "Ann <ann@example.com>", 1700000000, 1),
+                (c2.as_str(), "tab\there\u{e9}", "Bob <bob@example.com>", 1600000000, 3),
+            ]
+        );
+        assert_eq!(unquote_path("plain\\name"), "plain\\name");
+    }
+
+    #[test]
+    fn test_parse_unmerged_refs() {
+        let tip = "1".repeat(40);
+        let stdout = format!(
+            "refs/heads/wip\0{tip}\0refs/remotes/origin/wip\01700000000\0Ann Example\0<ann@example.com>\0Fix: a\tb\nrefs/heads/stacked\0{tip}\0refs/heads/wip\01\0x\0<x>\0x\nrefs/remotes/origin/HEAD\0{tip}\0\01\0x\0<x>\0x\n"
+        );
+        let branches = parse_unmerged_refs(stdout.as_bytes());
+        assert_eq!(branches.len(), 2);
+        assert_eq!(branches[0].name, "wip");
+        assert_eq!(branches[0].upstream.as_deref(), Some("refs/remotes/origin/wip"));
+        assert_eq!(branches[1].upstream, None);
+        assert_eq!(
+            branches[0].last_commit,
+            Some(CommitInfo {
+                date: 1700000000,
+                author_name: "Ann Example".to_string(),
+                author_email: "ann@example.com".to_string(),
+                subject: "Fix: a\tb".to_string(),
+            })
+        );
+        assert_eq!(branches[0].last_commit.as_ref().unwrap().age_days(1700000000 + 3 * 86400 - 1), 2);
+    }
+
+    #[test]
+    fn test_oid_hex_roundtrip() {
+        let hex = "0123456789abcdef0123456789abcdef01234567";
+        let oid = Oid::from_hex(hex).unwrap();
+        assert!(matches!(oid, Oid::Sha1(_)));
+        assert_eq!(oid.to_string(), hex);
+        assert!(Oid::from_hex("xyz").is_none());
+        assert!(matches!(Oid::from_hex(&"ab".repeat(32)), Some(Oid::Sha256(_))));
+    }
+
+    #[test]
+    fn test_parse_broken_refs() {
+        let stderr = "warning: ignoring broken ref refs/heads/bad\n\
+                      warning: ignoring ref with broken name refs/remotes/origin/x..y\n\
+                      warning: something else\n\
+                      warning: ignoring broken ref refs/tags/v1\n";
+        assert_eq!(
+            parse_broken_refs(stderr),
+            vec![
+                BrokenRef { refname: "refs/heads/bad".to_string(), reason: "ignoring broken ref".to_string() },
+                BrokenRef {
+                    refname: "refs/remotes/origin/x..y".to_string(),
+                    reason: "ignoring ref with broken name".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_classify_git_error() {
+        let repo = Path::new("/repo");
+        let missing = "fatal: missing blob object '4992ec75e90fc932ffa91ce592d640a7e443ebb4'".to_string();
+        assert!(matches!(
+            classify_git_error(repo, "rev-list", missing),
+            BranchWeightError::ObjectUnreadable { oid, .. } if oid == "4992ec75e90fc932ffa91ce592d640a7e443ebb4"
+        ));
+
+        let not_repo = "fatal: not a git repository (or any of the parent directories): .git".to_string();
+        assert!(matches!(classify_git_error(repo, "for-each-ref", not_repo), BranchWeightError::NotARepo(_)));
+
+        let other = "fatal: ambiguous argument 'nope'".to_string();
+        assert!(matches!(classify_git_error(repo, "rev-list", other), BranchWeightError::Git { .. }));
+    }
+
+    #[test]
+    fn test_parse_tree_edges_skips_gitlinks() {
+        let mut tree = Vec::new();
+        tree.extend_from_slice(b"100644 a.txt\0");
+        tree.extend_from_slice(&[1u8; 20]);
+        tree.extend_from_slice(b"160000 sub\0");
+        tree.extend_from_slice(&[2u8; 20]);
+        tree.extend_from_slice(b"40000 dir\0");
+        tree.extend_from_slice(&[3u8; 20]);
+
+        let edges = parse_tree_edges(&tree, 20);
+        assert_eq!(edges, vec![Oid::Sha1([1u8; 20]), Oid::Sha1([3u8; 20])]);
+    }
+
+    #[test]
+    fn test_second_walk_is_not_sized_again() {
+        let repo = std::env::temp_dir().join(format!("branch-weight-git-walk-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&repo);
+        std::fs::create_dir_all(&repo).unwrap();
+        let git = |args: &[&str]| {
+            assert!(Command::new("git").args(args).current_dir(&repo).status().unwrap().success(), "git {:?} failed", args)
+        };
+        git(&["init", "-q", "-b", "main"]);
+        git(&["config", "user.email", "t@example.com"]);
+        git(&["config", "user.name", "T"]);
+        std::fs::write(repo.join("a.txt"), "a\n").unwrap();
+        git(&["add", "."]);
+        git(&["commit", "-qm", "base"]);
+        git(&["checkout", "-qb", "feature"]);
+        std::fs::create_dir_all(repo.join("dir")).unwrap();
+        std::fs::write(repo.join("dir/b.txt"), "b\n").unwrap();
+        git(&["add", "."]);
+        git(&["commit", "-qm", "feature"]);
+
+        let real = RealGit::open(&repo).unwrap();
+        let walk = || real.get_unmerged_objects(&repo, &["feature".to_string()], &["main".to_string()], true).unwrap();
+        let first = walk();
+        assert_eq!(first.len(), 4);
+        let checked = real.cat_file.checked();
+        assert_eq!(checked, 4);
+
+        // as `--details` does for every branch it details
+        let second = walk();
+        assert_eq!(real.cat_file.checked(), checked);
+        assert!(first.iter().zip(second.iter()).all(|(a, b)| a == b));
+        assert_eq!(real.get_blob_sizes(&repo, &[first[0].oid.to_string()]).unwrap().len(), 0);
+        assert_eq!(real.cat_file.checked(), checked);
 
-        Ok(sizes)
+        let _ = std::fs::remove_dir_all(&repo);
     }
 }
@@ -0,0 +1,117 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Pointer files are capped below this size by the LFS spec, so only blobs
+// smaller than it need to be read.
+pub const POINTER_MAX_SIZE: u64 = 1024;
+
+const VERSIONS: [&str; 2] = ["https://git-lfs.github.com/spec/v1", "https://hawser.github.com/spec/v1"];
+
+// What a pointer file says about the content it stands in for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LfsPointer {
+    pub oid: String,
+    pub size: u64,
+}
+
+// "version <url>" first, then sorted "key value" lines, of which `oid
+// sha256:<hex>` and `size` are required.
+pub fn parse_pointer(content: &[u8]) -> Option<LfsPointer> {
+    if content.len() as u64 >= POINTER_MAX_SIZE {
+        return None;
+    }
+    let text = std::str::from_utf8(content).ok()?;
+    let mut lines = text.lines();
+    let version = lines.next()?.strip_prefix("version ")?;
+    if !VERSIONS.contains(&version) {
+        return None;
+    }
+
+    let (mut oid, mut size) = (None, None);
+    for line in lines {
+        let (key, value) = line.split_once(' ')?;
+        match key {
+            "oid" => {
+                let hex = value.strip_prefix("sha256:")?;
+                if hex.len() != 64 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+                    return None;
+                }
+                oid = Some(hex.to_ascii_lowercase());
+            }
+            "size" => size = Some(value.parse().ok()?),
+            _ => {}
+        }
+    }
+    Some(LfsPointer { oid: oid?, size: size? })
+}
+
+// The local object store git-lfs fills on fetch, `lfs/objects` in the
+// common git dir, so linked worktrees share it.
+pub struct LfsStore {
+    objects: PathBuf,
+}
+
+impl LfsStore {
+    pub fn open(git_dir: &Path) -> Self {
+        let common_dir = match fs::read_to_string(git_dir.join("commondir")) {
+            Ok(relative) => git_dir.join(relative.trim()),
+            Err(_) => git_dir.to_path_buf(),
+        };
+        LfsStore { objects: common_dir.join("lfs").join("objects") }
+    }
+
+    // Size on disk of the content, if it has been fetched.
+    pub fn local_size(&self, pointer: &LfsPointer) -> Option<u64> {
+        let path = self.objects.join(&pointer.oid[..2]).join(&pointer.oid[2..4]).join(&pointer.oid);
+        fs::metadata(path).ok().filter(|m| m.is_file()).map(|m| m.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OID: &str = "4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393";
+
+    #[test]
+    fn test_parse_pointer() {
+        let pointer = format!("version https://git-lfs.github.com/spec/v1\noid sha256:{}\nsize 12345\n", OID);
+        assert_eq!(
+            parse_pointer(pointer.as_bytes()),
+            Some(LfsPointer { oid: OID.to_string(), size: 12345 })
+        );
+
+        let with_extension = format!(
+            "version https://git-lfs.github.com/spec/v1\next-0-foo sha256:{}\noid sha256:{}\nsize 7\n",
+            OID, OID
+        );
+        assert_eq!(parse_pointer(with_extension.as_bytes()).map(|p| p.size), Some(7));
+
+        assert_eq!(parse_pointer(b"version https://git-lfs.github.com/spec/v1\nsize 7\n"), None);
+        assert_eq!(parse_pointer(format!("oid sha256:{}\nsize 7\n", OID).as_bytes()), None);
+        assert_eq!(parse_pointer(b"version https://git-lfs.github.com/spec/v1\noid sha256:abc\nsize 7\n"), None);
+        assert_eq!(parse_pointer(&[0xff, 0xfe]), None);
+    }
+
+    #[test]
+    fn test_local_size() {
+        let git_dir = std::env::temp_dir().join(format!("branch-weight-lfs-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&git_dir);
+        let dir = git_dir.join("lfs/objects").join(&OID[..2]).join(&OID[2..4]);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(OID), vec![0u8; 300]).unwrap();
+
+        let store = LfsStore::open(&git_dir);
+        assert_eq!(store.local_size(&LfsPointer { oid: OID.to_string(), size: 300 }), Some(300));
+        let other = "0".repeat(64);
+        assert_eq!(store.local_size(&LfsPointer { oid: other, size: 300 }), None);
+
+        // a linked worktree's git dir points at the common one
+        let worktree = git_dir.join("worktrees/wt");
+        fs::create_dir_all(&worktree).unwrap();
+        fs::write(worktree.join("commondir"), "../..\n").unwrap();
+        assert_eq!(LfsStore::open(&worktree).local_size(&LfsPointer { oid: OID.to_string(), size: 1 }), Some(300));
+
+        let _ = fs::remove_dir_all(&git_dir);
+    }
+}
@@ -0,0 +1,24 @@
+// Library behind the `git-branch-weight` CLI. `analyze_branches` runs the
+// shared walk for a `GitOps` backend; the `report` writers turn the result
+// into the JSON files the CLI produces.
+pub mod cache;
+mod cat_file;
+pub mod error;
+pub mod git;
+pub mod lfs;
+pub mod native;
+pub mod objects;
+pub mod refs;
+pub mod report;
+
+pub use error::{BranchWeightError, Result};
+pub use git::{GitOps, ObjectKind, RealGit};
+pub use native::NativeGit;
+pub use objects::{
+    analyze_branch_details, analyze_branches, find_large_objects, Analysis, AnalysisOptions, BranchDetail, BranchError,
+    BranchStatus, BranchWeight, CacheStats, FileWeight, LargeObject, LfsStats, PathBreakdown, SortKey, Upstream,
+};
+pub use report::{
+    write_cleanup_plan, write_detailed_report, write_errors, write_large_objects, write_reclaim_report, write_reports,
+    write_top_files,
+};
@@ -1,12 +1,9 @@
-mod git;
-mod objects;
-mod report;
-
-use anyhow::Result;
-use clap::Parser;
-use git::{GitOps, RealGit};
-use std::path::PathBuf;
-use std::time::Instant;
+use clap::{Parser, ValueEnum};
+use git_branch_weight::objects::{self, BranchStatus, PlanGoal, SortKey};
+use git_branch_weight::{report, AnalysisOptions, BranchWeightError, GitOps, NativeGit, ObjectKind, RealGit, Result};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
 
 #[derive(Parser, Debug)]
 #[command(name = "git-branch-weight")]
@@ -18,47 +15,241 @@ struct Args {
     #[arg(short, long)]
     out: Option<PathBuf>,
 
-    #[arg(short = 'B', long)]
-    branch: Option<String>,
+    #[arg(short = 'B', long = "base", visible_alias = "branch")]
+    base: Vec<String>,
+
+    #[arg(long)]
+    include: Vec<String>,
+
+    #[arg(long)]
+    exclude: Vec<String>,
+
+    #[arg(long)]
+    remote: Option<String>,
+
+    #[arg(long)]
+    group_upstream: bool,
 
     #[arg(short = 'y', long)]
     no_prompt: bool,
 
     #[arg(short, long, default_value = "0")]
     details: usize,
+
+    #[arg(long, default_value = "10")]
+    top_files: usize,
+
+    #[arg(long, value_enum, default_value = "git")]
+    backend: Backend,
+
+    #[arg(long, value_delimiter = ',', default_value = "blob", value_parser = parse_object_kind)]
+    object_types: Vec<ObjectKind>,
+
+    #[arg(long)]
+    delta_info: bool,
+
+    #[arg(long)]
+    path_breakdown: bool,
+
+    #[arg(long)]
+    lfs: bool,
+
+    #[arg(long)]
+    strict: bool,
+
+    #[arg(long)]
+    no_cache: bool,
+
+    #[arg(long, conflicts_with = "no_cache")]
+    cache_dir: Option<PathBuf>,
+
+    #[arg(long, value_delimiter = ',')]
+    reclaim: Vec<String>,
+
+    #[arg(long, value_parser = parse_size)]
+    min_object_size: Option<u64>,
+
+    #[arg(long, value_parser = parse_age)]
+    older_than: Option<Duration>,
+
+    #[arg(long, value_parser = parse_age)]
+    newer_than: Option<Duration>,
+
+    #[arg(long, default_value = "total", value_parser = parse_sort_key)]
+    sort_by: SortKey,
+
+    #[arg(long, value_parser = parse_size, conflicts_with = "plan_top")]
+    plan_target: Option<u64>,
+
+    #[arg(long)]
+    plan_top: Option<usize>,
+}
+
+// Accepts plain byte counts or a number with a KB/MB/GB/TB suffix (powers of
+// 1024, matching the MB figures in the reports).
+fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: f64 = number.parse().map_err(|_| format!("invalid size '{}'", s))?;
+    let multiplier = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1u64,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        "T" | "TB" | "TIB" => 1 << 40,
+        other => return Err(format!("unknown size unit '{}'", other)),
+    };
+    Ok((number * multiplier as f64) as u64)
+}
+
+// A number of days, or a number with an h/d/w/y suffix (a year being 365
+// days), e.g. "90d".
+fn parse_age(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: f64 = number.parse().map_err(|_| format!("invalid age '{}'", s))?;
+    let hours = match unit.trim().to_ascii_lowercase().as_str() {
+        "h" => 1.0,
+        "" | "d" => 24.0,
+        "w" => 24.0 * 7.0,
+        "y" => 24.0 * 365.0,
+        other => return Err(format!("unknown age unit '{}' (expected h, d, w or y)", other)),
+    };
+    Ok(Duration::from_secs_f64(number * hours * 3600.0))
+}
+
+fn parse_sort_key(s: &str) -> Result<SortKey, String> {
+    SortKey::parse(s).ok_or_else(|| format!("unknown sort key '{}' (expected total, unique, age or score)", s))
+}
+
+fn parse_object_kind(s: &str) -> Result<ObjectKind, String> {
+    ObjectKind::parse(s).ok_or_else(|| format!("unknown object type '{}' (expected blob, tree, commit or tag)", s))
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Backend {
+    Git,
+    Native,
+}
+
+fn main() -> ExitCode {
+    match try_main() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            let code = e.exit_code();
+            eprintln!("Error: {:#}", anyhow::Error::from(e));
+            ExitCode::from(code)
+        }
+    }
 }
 
-fn main() -> Result<()> {
+fn try_main() -> Result<()> {
     let args = Args::parse();
     let start = Instant::now();
 
-    let repo_path = args.repo.canonicalize()?;
-    let out_dir = args.out.unwrap_or_else(|| repo_path.join("unmerged-branches-size-report"));
+    let repo_path = args.repo.canonicalize().map_err(|_| BranchWeightError::NotARepo(args.repo.clone()))?;
+    let out_dir = args.out.clone().unwrap_or_else(|| repo_path.join("unmerged-branches-size-report"));
 
     println!("Opening repository: {}", repo_path.display());
 
-    let git = RealGit;
+    match args.backend {
+        Backend::Git => run(&RealGit::open(&repo_path)?, &args, &repo_path, &out_dir)?,
+        Backend::Native => run(&NativeGit::open(&repo_path)?, &args, &repo_path, &out_dir)?,
+    }
 
-    let default_branch = match &args.branch {
-        Some(b) => b.clone(),
-        None => git.detect_default_branch(&repo_path)?,
-    };
+    println!("Done in {:.1}s", start.elapsed().as_secs_f64());
+    println!("Reports saved to: {}", out_dir.display());
+
+    Ok(())
+}
 
-    println!("Default branch: {}", default_branch);
+fn run<G: GitOps>(git: &G, args: &Args, repo_path: &Path, out_dir: &Path) -> Result<()> {
+    let mut options = AnalysisOptions::new()
+        .bases(&args.base)
+        .object_types(args.object_types.iter().copied())
+        .delta_info(args.delta_info)
+        .path_breakdown(args.path_breakdown)
+        .lfs(args.lfs)
+        .group_upstreams(args.group_upstream)
+        .sort_by(args.sort_by);
+    for pattern in &args.include {
+        options = options.include(pattern);
+    }
+    for pattern in &args.exclude {
+        options = options.exclude(pattern);
+    }
+    if let Some(remote) = &args.remote {
+        options = options.remote(remote);
+    }
+    if let Some(age) = args.older_than {
+        options = options.older_than(age);
+    }
+    if let Some(age) = args.newer_than {
+        options = options.newer_than(age);
+    }
+    if !args.no_cache {
+        let cache_dir = match &args.cache_dir {
+            Some(dir) => dir.clone(),
+            None => git.git_dir(repo_path)?.join("branch-weight-cache"),
+        };
+        options = options.cache_dir(cache_dir);
+    }
+    let analysis = objects::analyze_branches(git, repo_path, &options)?;
+
+    std::fs::create_dir_all(out_dir).map_err(|source| BranchWeightError::OutputNotWritable {
+        path: out_dir.to_path_buf(),
+        source,
+    })?;
+    report::write_reports(out_dir, &analysis)?;
+    let mut errors = analysis.failures();
+
+    if !args.reclaim.is_empty() {
+        let mut selected = Vec::new();
+        for name in &args.reclaim {
+            match analysis.branch_index(name) {
+                Some(idx) if matches!(analysis.statuses[idx as usize], BranchStatus::Failed(_)) => {
+                    return Err(anyhow::anyhow!("Branch '{}' could not be scanned, see errors.json", name).into());
+                }
+                Some(idx) => selected.push(idx),
+                None => return Err(anyhow::anyhow!("Branch '{}' is not among the unmerged branches", name).into()),
+            }
+        }
+        let reclaim = analysis.reclaimable_if_deleted(&selected);
+        report::write_reclaim_report(out_dir, &analysis, &reclaim)?;
+    }
 
-    let branch_weights = objects::analyze_branches(&git, &repo_path, &default_branch)?;
+    let goal = match (args.plan_target, args.plan_top) {
+        (Some(target), _) => Some(PlanGoal::TargetBytes(target)),
+        (None, Some(k)) => Some(PlanGoal::TopK(k)),
+        (None, None) => None,
+    };
+    if let Some(goal) = goal {
+        let steps = analysis.plan_cleanup(goal);
+        report::write_cleanup_plan(out_dir, &analysis, goal, &steps)?;
+    }
 
-    std::fs::create_dir_all(&out_dir)?;
-    report::write_reports(&out_dir, &branch_weights)?;
+    if let Some(min_size) = args.min_object_size {
+        let large = objects::find_large_objects(git, repo_path, &analysis, min_size)?;
+        report::write_large_objects(out_dir, &analysis, min_size, &large)?;
+    }
 
     if args.details > 0 {
         println!("Analyzing top {} branches for commits...", args.details);
-        let details = objects::analyze_branch_details(&git, &repo_path, &branch_weights, &default_branch, args.details)?;
-        report::write_detailed_report(&out_dir, &details)?;
+        let (details, detail_errors) =
+            objects::analyze_branch_details(git, repo_path, &analysis, args.details, args.top_files)?;
+        report::write_detailed_report(out_dir, &details)?;
+        if args.top_files > 0 {
+            report::write_top_files(out_dir, &details)?;
+        }
+        errors.extend(detail_errors);
     }
 
-    println!("Done in {:.1}s", start.elapsed().as_secs_f64());
-    println!("Reports saved to: {}", out_dir.display());
+    report::write_errors(out_dir, &errors)?;
+    if args.strict && !errors.is_empty() {
+        return Err(BranchWeightError::BranchFailures(errors.len()));
+    }
 
     Ok(())
 }
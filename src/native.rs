@@ -0,0 +1,723 @@
+use crate::git::{
+    BlobChange, BranchRef, BrokenRef, CommitBlobs, CommitInfo, GitOps, ObjectKind, Oid, UnmergedObject, UnmergedObjects,
+};
+use crate::error::{BranchWeightError, Result};
+use anyhow::Context;
+use gix::hash::{oid, ObjectId};
+use gix::objs::tree::EntryMode;
+use gix::odb::pack;
+use rustc_hash::{FxHashMap, FxHashSet};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+type CommitParents = (ObjectId, Vec<ObjectId>);
+type BranchRefs = (Vec<(String, ObjectId)>, Vec<BrokenRef>);
+
+// Every object a walk has met, with its index in the table once it is in
+// one. Merged objects, and trees whose entries are still being walked, have
+// none.
+type Seen = FxHashMap<ObjectId, Option<u32>>;
+
+// The table a walk fills, plus the delta bases to point at once every object
+// has its index.
+#[derive(Default)]
+struct Recorded {
+    objects: UnmergedObjects,
+    delta_bases: Vec<(u32, ObjectId)>,
+}
+
+impl Recorded {
+    fn push(&mut self, (object, delta_base): (UnmergedObject, Option<ObjectId>), path: Option<String>) -> u32 {
+        let node = self.objects.push(object, path);
+        if let Some(base) = delta_base {
+            self.delta_bases.push((node, base));
+        }
+        node
+    }
+}
+
+// In-process backend: reads refs, commits and trees with gitoxide and takes
+// on-disk object sizes straight from the pack indices, so no git process is
+// ever spawned. The repository is bound at `open` time; the `repo` argument of
+// the `GitOps` methods is ignored.
+pub struct NativeGit {
+    repo: gix::ThreadSafeRepository,
+    packs: Packs,
+}
+
+impl NativeGit {
+    pub fn open(repo: &Path) -> Result<Self> {
+        let repo = gix::open(repo).map_err(|err| match err {
+            gix::open::Error::NotARepository { .. } => BranchWeightError::NotARepo(repo.to_path_buf()),
+            err => anyhow::Error::new(err)
+                .context(format!("Failed to open repository {}", repo.display()))
+                .into(),
+        })?;
+        let packs = Packs::load(&repo)?;
+        Ok(NativeGit { repo: repo.into(), packs })
+    }
+
+    fn local(&self) -> gix::Repository {
+        let mut repo = self.repo.to_thread_local();
+        repo.object_cache_size_if_unset(4 * 1024 * 1024);
+        repo
+    }
+
+    fn resolve(&self, repo: &gix::Repository, spec: &str) -> Result<ObjectId> {
+        if let Ok(id) = ObjectId::from_hex(spec.as_bytes()) {
+            return Ok(id);
+        }
+        let id = repo
+            .rev_parse_single(spec)
+            .with_context(|| format!("Failed to resolve {}", spec))?;
+        Ok(id.detach())
+    }
+
+    // Commits reachable from `tips` but from none of `exclude`, plus the
+    // excluded parents at the edge of that set (what `rev-list` calls the
+    // boundary).
+    fn unmerged_commits(
+        &self,
+        repo: &gix::Repository,
+        tips: &[ObjectId],
+        exclude: &[ObjectId],
+    ) -> Result<(Vec<CommitParents>, Vec<ObjectId>)> {
+        let mut commits = Vec::new();
+        let walk = repo
+            .rev_walk(tips.iter().copied())
+            .sorting(gix::revision::walk::Sorting::ByCommitTime(Default::default()))
+            .with_hidden(exclude.iter().copied())
+            .all()
+            .context("Failed to start commit walk")?;
+
+        for info in walk {
+            let info = info.context("Failed to walk commits")?;
+            commits.push((info.id, info.parent_ids.to_vec()));
+        }
+
+        let walked: FxHashSet<ObjectId> = commits.iter().map(|(id, _)| *id).collect();
+        let mut boundary: Vec<ObjectId> = commits
+            .iter()
+            .flat_map(|(_, parents)| parents.iter().copied())
+            .filter(|p| !walked.contains(p))
+            .collect();
+        boundary.sort();
+        boundary.dedup();
+
+        Ok((commits, boundary))
+    }
+
+    // Refs under refs/heads and refs/remotes with their peeled tips, sorted by
+    // refname like `git for-each-ref`, plus the ones that could not be read.
+    fn branch_refs(&self, repo: &gix::Repository) -> Result<BranchRefs> {
+        let mut refs = Vec::new();
+        let mut broken = Vec::new();
+        let platform = repo.references().context("Failed to read references")?;
+
+        for reference in platform.all().context("Failed to read references")? {
+            let mut reference = match reference {
+                Ok(reference) => reference,
+                Err(err) => {
+                    use gix::refs::file::iter::loose_then_packed::Error;
+                    let refname = match err.downcast_ref::<Error>() {
+                        Some(Error::ReferenceCreation { relative_path, .. }) => {
+                            gix::path::to_unix_separators_on_windows(gix::path::into_bstr(relative_path.as_path()))
+                                .to_string()
+                        }
+                        _ => "(unreadable)".to_string(),
+                    };
+                    // same wording as git for a ref file it cannot parse
+                    let reason = "ignoring broken ref".to_string();
+                    broken.push(BrokenRef { refname, reason });
+                    continue;
+                }
+            };
+            let refname = reference.name().as_bstr().to_string();
+
+            if !(refname.starts_with("refs/heads/") || refname.starts_with("refs/remotes/")) {
+                continue;
+            }
+            if refname.ends_with("/HEAD") {
+                continue;
+            }
+
+            if let Some(target) = reference.target().try_id() {
+                if !repo.has_object(target) {
+                    let reason = format!("tip {} is missing", target);
+                    broken.push(BrokenRef { refname, reason });
+                    continue;
+                }
+            }
+            match reference.peel_to_id() {
+                Ok(id) => refs.push((refname, id.detach())),
+                Err(err) => broken.push(BrokenRef { refname, reason: err.to_string() }),
+            }
+        }
+
+        refs.sort();
+        broken.sort_by(|a, b| a.refname.cmp(&b.refname));
+        Ok((refs, broken))
+    }
+
+    fn commit_tree(&self, repo: &gix::Repository, commit: ObjectId) -> Result<ObjectId> {
+        let id = commit;
+        let commit = repo.find_commit(id).map_err(|err| BranchWeightError::unreadable(id, err))?;
+        let tree = commit.tree_id().map_err(|err| BranchWeightError::unreadable(id, err))?;
+        Ok(tree.detach())
+    }
+
+    fn tree_entries(&self, repo: &gix::Repository, tree: ObjectId) -> Result<Vec<(EntryMode, Vec<u8>, ObjectId)>> {
+        let id = tree;
+        let tree = repo.find_tree(id).map_err(|err| BranchWeightError::unreadable(id, err))?;
+        let mut entries = Vec::new();
+        for entry in gix::objs::TreeRefIter::from_bytes(&tree.data) {
+            let entry = entry.map_err(|err| BranchWeightError::unreadable(id, err))?;
+            entries.push((entry.mode, entry.filename.to_vec(), entry.oid.to_owned()));
+        }
+        Ok(entries)
+    }
+
+    // Walks `tree` depth first, skipping anything already in `seen`. Visited
+    // trees and blobs are recorded in `out` when given, each tree after its
+    // entries so they all have an index by then; gitlinks are skipped just
+    // like `rev-list --objects`. Entries are taken in tree order and marked
+    // when visited, as rev-list does, so a blob found under several paths
+    // gets the same first one.
+    fn walk_tree(
+        &self,
+        repo: &gix::Repository,
+        tree: ObjectId,
+        seen: &mut Seen,
+        mut out: Option<&mut Recorded>,
+        delta_info: bool,
+    ) -> Result<()> {
+        enum Step {
+            Visit(ObjectId, bool, String),
+            Record(ObjectId, Vec<ObjectId>),
+        }
+
+        let mut stack = vec![Step::Visit(tree, true, String::new())];
+        while let Some(step) = stack.pop() {
+            let (id, is_tree, path) = match step {
+                Step::Visit(id, is_tree, path) => (id, is_tree, path),
+                Step::Record(id, entries) => {
+                    if let Some(out) = out.as_deref_mut() {
+                        let children: Vec<u32> =
+                            entries.iter().filter_map(|child| seen.get(child).copied().flatten()).collect();
+                        let node = out.push(self.object(repo, id, ObjectKind::Tree, delta_info)?, None);
+                        out.objects.set_children(node, children);
+                        seen.insert(id, Some(node));
+                    }
+                    continue;
+                }
+            };
+            match seen.entry(id) {
+                Entry::Occupied(_) => continue,
+                Entry::Vacant(entry) => entry.insert(None),
+            };
+            if !is_tree {
+                if let Some(out) = out.as_deref_mut() {
+                    let node = out.push(self.object(repo, id, ObjectKind::Blob, delta_info)?, Some(path));
+                    seen.insert(id, Some(node));
+                }
+                continue;
+            }
+
+            let entries: Vec<_> = self
+                .tree_entries(repo, id)?
+                .into_iter()
+                .filter(|(mode, _, _)| mode.is_tree() || mode.is_blob_or_symlink())
+                .collect();
+            let named = out.is_some();
+            if named {
+                stack.push(Step::Record(id, entries.iter().map(|(_, _, child)| *child).collect()));
+            }
+            for (mode, name, child) in entries.into_iter().rev() {
+                if seen.contains_key(&child) {
+                    continue;
+                }
+                // only recorded objects need their path spelled out
+                let path = if named {
+                    let name = String::from_utf8_lossy(&name);
+                    if path.is_empty() { name.into_owned() } else { format!("{}/{}", path, name) }
+                } else {
+                    String::new()
+                };
+                stack.push(Step::Visit(child, mode.is_tree(), path));
+            }
+        }
+        Ok(())
+    }
+
+    // The object as the table holds it, with its delta base by id when
+    // delta info is wanted.
+    fn object(
+        &self,
+        repo: &gix::Repository,
+        id: ObjectId,
+        kind: ObjectKind,
+        delta_info: bool,
+    ) -> Result<(UnmergedObject, Option<ObjectId>)> {
+        let (raw_size, delta_base) = if delta_info {
+            let header = repo.find_header(id).map_err(|err| BranchWeightError::unreadable(id, err))?;
+            (header.size(), self.packs.delta_base(&id))
+        } else {
+            (0, None)
+        };
+
+        let size = self
+            .packs
+            .disk_size(&id)
+            .ok_or_else(|| BranchWeightError::missing_object(id))?;
+
+        Ok((UnmergedObject { oid: to_oid(&id), kind, size, raw_size, delta_base: None }, delta_base))
+    }
+
+    // Mirrors `git diff-tree -r --diff-filter=AM`: blobs that were added or
+    // modified in `new` relative to `old`, with their paths below `prefix`.
+    // Type changes are not reported.
+    fn changed_blobs(
+        &self,
+        repo: &gix::Repository,
+        old: Option<ObjectId>,
+        new: ObjectId,
+        prefix: &[u8],
+        out: &mut Vec<(ObjectId, Vec<u8>)>,
+    ) -> Result<()> {
+        let old_entries: HashMap<Vec<u8>, (EntryMode, ObjectId)> = match old {
+            Some(old) => self
+                .tree_entries(repo, old)?
+                .into_iter()
+                .map(|(mode, name, id)| (name, (mode, id)))
+                .collect(),
+            None => HashMap::new(),
+        };
+
+        for (mode, name, id) in self.tree_entries(repo, new)? {
+            let previous = old_entries.get(&name).copied();
+            if previous == Some((mode, id)) {
+                continue;
+            }
+            let path = if prefix.is_empty() { name } else { [prefix, b"/", &name].concat() };
+            if mode.is_tree() {
+                let old_tree = previous.filter(|(m, _)| m.is_tree()).map(|(_, id)| id);
+                self.changed_blobs(repo, old_tree, id, &path, out)?;
+            } else if mode.is_blob_or_symlink() {
+                match previous {
+                    // a mode change adds no content
+                    Some((_, previous_id)) if previous_id == id => {}
+                    None => out.push((id, path)),
+                    Some((m, _)) if m.is_tree() => out.push((id, path)),
+                    Some((m, _)) if m.is_link() == mode.is_link() && !m.is_commit() => out.push((id, path)),
+                    Some(_) => {}
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl GitOps for NativeGit {
+    fn list_refs(&self, _repo: &Path) -> Result<Vec<(String, String)>> {
+        let repo = self.local();
+        let (refs, _) = self.branch_refs(&repo)?;
+        let refs = refs
+            .into_iter()
+            .map(|(refname, id)| (refname, id.to_string()))
+            .collect();
+        Ok(refs)
+    }
+
+    fn get_branches(&self, _repo: &Path, bases: &[String]) -> Result<(Vec<BranchRef>, Vec<BrokenRef>)> {
+        let repo = self.local();
+        let bases = bases
+            .iter()
+            .map(|base| self.resolve(&repo, base))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut merged: FxHashSet<ObjectId> = FxHashSet::default();
+        for info in repo.rev_walk(bases).all().context("Failed to walk base branches")? {
+            merged.insert(info.context("Failed to walk base branches")?.id);
+        }
+
+        let (refs, mut broken) = self.branch_refs(&repo)?;
+        let mut branches = Vec::new();
+        for (refname, id) in refs.into_iter().filter(|(_, id)| !merged.contains(id)) {
+            // a tip that cannot be read as a commit fails only its own branch
+            let last_commit = match commit_info(&repo, id) {
+                Ok(info) => Some(info),
+                Err(err) => {
+                    broken.push(BrokenRef { refname, reason: err.to_string() });
+                    continue;
+                }
+            };
+            let upstream = upstream(&repo, &refname);
+            branches.push(BranchRef { last_commit, upstream, ..BranchRef::new(refname, id.to_string()) });
+        }
+        broken.sort_by(|a, b| a.refname.cmp(&b.refname));
+        Ok((branches, broken))
+    }
+
+    fn get_unmerged_objects(
+        &self,
+        _repo: &Path,
+        tips: &[String],
+        exclude: &[String],
+        delta_info: bool,
+    ) -> Result<UnmergedObjects> {
+        let repo = self.local();
+        let tips = tips
+            .iter()
+            .map(|tip| self.resolve(&repo, tip))
+            .collect::<Result<Vec<_>>>()?;
+        let exclude = exclude
+            .iter()
+            .map(|e| self.resolve(&repo, e))
+            .collect::<Result<Vec<_>>>()?;
+        let (commits, boundary) = self.unmerged_commits(&repo, &tips, &exclude)?;
+
+        let mut seen = Seen::default();
+        for commit in boundary {
+            let tree = self.commit_tree(&repo, commit)?;
+            self.walk_tree(&repo, tree, &mut seen, None, false)?;
+        }
+
+        let mut out = Recorded::default();
+        let mut trees = Vec::with_capacity(commits.len());
+        for (commit, _) in &commits {
+            let tree = self.commit_tree(&repo, *commit)?;
+            self.walk_tree(&repo, tree, &mut seen, Some(&mut out), delta_info)?;
+            trees.push(seen[&tree]);
+        }
+
+        // commits go last, when their trees and parents all have an index
+        let first = out.objects.len();
+        for (i, (commit, _)) in commits.iter().enumerate() {
+            seen.insert(*commit, Some((first + i) as u32));
+        }
+        for ((commit, parents), tree) in commits.iter().zip(trees) {
+            let node = out.push(self.object(&repo, *commit, ObjectKind::Commit, delta_info)?, None);
+            let parents = parents.iter().filter_map(|parent| seen.get(parent).copied().flatten());
+            out.objects.set_children(node, tree.into_iter().chain(parents));
+        }
+
+        let Recorded { mut objects, delta_bases } = out;
+        for (node, base) in delta_bases {
+            let base = match seen.get(&base) {
+                Some(&Some(base)) => base,
+                _ => objects.push_base(to_oid(&base)),
+            };
+            objects.set_delta_base(node, base);
+        }
+        Ok(objects)
+    }
+
+    fn get_unmerged_commits(&self, _repo: &Path, branch: &str, exclude: &[String]) -> Result<Vec<CommitBlobs>> {
+        let repo = self.local();
+        let tip = self.resolve(&repo, branch)?;
+        let exclude = exclude
+            .iter()
+            .map(|e| self.resolve(&repo, e))
+            .collect::<Result<Vec<_>>>()?;
+        let (commits, _) = self.unmerged_commits(&repo, &[tip], &exclude)?;
+
+        let mut results: Vec<CommitBlobs> = Vec::new();
+
+        for (commit, parents) in commits {
+            if parents.len() != 1 {
+                continue;
+            }
+
+            let old = self.commit_tree(&repo, parents[0])?;
+            let new = self.commit_tree(&repo, commit)?;
+            let mut changed = Vec::new();
+            self.changed_blobs(&repo, Some(old), new, b"", &mut changed)?;
+
+            if !changed.is_empty() {
+                let blobs = changed
+                    .into_iter()
+                    .filter_map(|(id, _)| self.packs.disk_size(&id).map(|size| (id.to_string(), size)))
+                    .collect();
+                results.push(CommitBlobs { commit: commit.to_string(), blobs });
+            }
+        }
+
+        Ok(results)
+    }
+
+    fn get_small_blobs(&self, _repo: &Path, oids: &[Oid], max_size: u64) -> Result<Vec<(Oid, Vec<u8>)>> {
+        let repo = self.local();
+        let mut blobs = Vec::new();
+        for oid in oids {
+            let id = ObjectId::from_bytes_or_panic(oid.as_bytes());
+            let header = repo.find_header(id).map_err(|err| BranchWeightError::unreadable(id, err))?;
+            if header.kind() != gix::object::Kind::Blob || header.size() >= max_size {
+                continue;
+            }
+            let object = repo.find_object(id).map_err(|err| BranchWeightError::unreadable(id, err))?;
+            blobs.push((*oid, object.detach().data));
+        }
+        Ok(blobs)
+    }
+
+    fn find_blob_changes(
+        &self,
+        _repo: &Path,
+        tips: &[String],
+        exclude: &[String],
+        blobs: &[Oid],
+    ) -> Result<Vec<BlobChange>> {
+        if blobs.is_empty() || tips.is_empty() {
+            return Ok(Vec::new());
+        }
+        let repo = self.local();
+        let tips = tips
+            .iter()
+            .map(|tip| self.resolve(&repo, tip))
+            .collect::<Result<Vec<_>>>()?;
+        let exclude = exclude
+            .iter()
+            .map(|e| self.resolve(&repo, e))
+            .collect::<Result<Vec<_>>>()?;
+        let (commits, _) = self.unmerged_commits(&repo, &tips, &exclude)?;
+        let generations = generations(&commits);
+        let wanted: FxHashSet<Oid> = blobs.iter().copied().collect();
+
+        let mut changes = Vec::new();
+        for (commit, parents) in commits {
+            if parents.len() > 1 {
+                continue;
+            }
+            let old = parents.first().map(|p| self.commit_tree(&repo, *p)).transpose()?;
+            let new = self.commit_tree(&repo, commit)?;
+            let mut changed = Vec::new();
+            self.changed_blobs(&repo, old, new, b"", &mut changed)?;
+            changed.retain(|(id, _)| wanted.contains(&to_oid(id)));
+            if changed.is_empty() {
+                continue;
+            }
+
+            let object = repo.find_commit(commit).map_err(|err| BranchWeightError::unreadable(commit, err))?;
+            let author = object.author().map_err(|err| BranchWeightError::unreadable(commit, err))?;
+            let author = format!("{} <{}>", author.name, author.email);
+            let time = object.time().map_err(|err| BranchWeightError::unreadable(commit, err))?.seconds;
+            for (id, path) in changed {
+                changes.push(BlobChange {
+                    blob: to_oid(&id),
+                    path: String::from_utf8_lossy(&path).into_owned(),
+                    commit: commit.to_string(),
+                    author: author.clone(),
+                    time,
+                    generation: generations[&commit],
+                });
+            }
+        }
+        Ok(changes)
+    }
+
+    fn resolve_commit(&self, _repo: &Path, rev: &str) -> Result<Option<String>> {
+        let repo = self.local();
+        Ok(repo
+            .rev_parse_single(format!("{}^{{commit}}", rev).as_str())
+            .ok()
+            .map(|id| id.to_string()))
+    }
+
+    fn git_dir(&self, _repo: &Path) -> Result<PathBuf> {
+        Ok(self.repo.git_dir().to_path_buf())
+    }
+}
+
+// What `%(upstream)` names for a local branch, through the remote's fetch
+// refspecs, kept only when it is a remote-tracking branch.
+fn upstream(repo: &gix::Repository, refname: &str) -> Option<String> {
+    if !refname.starts_with("refs/heads/") {
+        return None;
+    }
+    let name = gix::refs::FullName::try_from(refname).ok()?;
+    let tracking = repo
+        .branch_remote_tracking_ref_name(name.as_ref(), gix::remote::Direction::Fetch)?
+        .ok()?;
+    let tracking = tracking.as_bstr().to_string();
+    tracking.starts_with("refs/remotes/").then_some(tracking)
+}
+
+// Longest chain of walked commits ending at each commit, counting it, the
+// same numbers `git log --topo-order --reverse` yields parents first.
+fn generations(commits: &[CommitParents]) -> FxHashMap<ObjectId, u32> {
+    let parents: FxHashMap<ObjectId, &[ObjectId]> = commits.iter().map(|(id, p)| (*id, p.as_slice())).collect();
+    let mut generations: FxHashMap<ObjectId, u32> = FxHashMap::default();
+    for (id, _) in commits {
+        let mut stack = vec![*id];
+        while let Some(&top) = stack.last() {
+            if generations.contains_key(&top) {
+                stack.pop();
+                continue;
+            }
+            let pending: Vec<ObjectId> = parents[&top]
+                .iter()
+                .filter(|p| parents.contains_key(*p) && !generations.contains_key(*p))
+                .copied()
+                .collect();
+            if pending.is_empty() {
+                let generation = parents[&top].iter().filter_map(|p| generations.get(p)).max().map_or(1, |g| g + 1);
+                generations.insert(top, generation);
+                stack.pop();
+            } else {
+                stack.extend(pending);
+            }
+        }
+    }
+    generations
+}
+
+// The same details `for-each-ref` gives for a tip: committer date, author
+// and the message's first paragraph folded onto one line.
+fn commit_info(repo: &gix::Repository, id: ObjectId) -> Result<CommitInfo> {
+    let unreadable = |err: &dyn std::fmt::Display| BranchWeightError::unreadable(id, err);
+    let commit = repo.find_commit(id).map_err(|err| unreadable(&err))?;
+    let author = commit.author().map_err(|err| unreadable(&err))?;
+    let date = commit.time().map_err(|err| unreadable(&err))?.seconds;
+    let message = commit.message().map_err(|err| unreadable(&err))?;
+    Ok(CommitInfo {
+        date,
+        author_name: author.name.to_string(),
+        author_email: author.email.to_string(),
+        subject: message.summary().to_string(),
+    })
+}
+
+// `objects_dir` followed by the object directories it borrows from through
+// `info/alternates` (as `git clone --shared` or `--reference` set up),
+// following chained alternates up to five levels deep like git does.
+fn object_dirs(objects_dir: &Path) -> Vec<PathBuf> {
+    let objects_dir = objects_dir.canonicalize().unwrap_or_else(|_| objects_dir.to_path_buf());
+    let mut dirs = vec![objects_dir.clone()];
+    let mut level = vec![objects_dir];
+    for _ in 0..5 {
+        let mut next = Vec::new();
+        for dir in &level {
+            let Ok(alternates) = std::fs::read_to_string(dir.join("info/alternates")) else { continue };
+            for line in alternates.lines().map(str::trim) {
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                // relative entries are relative to the directory listing them
+                let alternate = dir.join(line);
+                let alternate = alternate.canonicalize().unwrap_or(alternate);
+                if !dirs.contains(&alternate) {
+                    dirs.push(alternate.clone());
+                    next.push(alternate);
+                }
+            }
+        }
+        level = next;
+    }
+    dirs
+}
+
+fn to_oid(id: &oid) -> Oid {
+    Oid::from_bytes(id.as_bytes()).expect("gix object ids are 20 or 32 bytes")
+}
+
+// Answers `%(objectsize:disk)` and `%(deltabase)` without git: the disk size
+// is the distance to the next entry in the pack for packed objects and the
+// file size for loose ones; delta bases come from the pack entry headers.
+struct Packs {
+    // the repository's own object directory first, then its alternates
+    objects_dirs: Vec<PathBuf>,
+    packs: Vec<Pack>,
+}
+
+struct Pack {
+    index: pack::index::File,
+    data: pack::data::File,
+    // (pack offset, index entry) sorted by offset
+    offsets: Vec<(pack::data::Offset, pack::index::EntryIndex)>,
+    end: u64,
+}
+
+impl Pack {
+    fn locate(&self, offset: pack::data::Offset) -> Option<usize> {
+        self.offsets.binary_search_by_key(&offset, |&(o, _)| o).ok()
+    }
+}
+
+impl Packs {
+    fn load(repo: &gix::Repository) -> Result<Self> {
+        let objects_dirs = object_dirs(&repo.common_dir().join("objects"));
+        let hash_kind = repo.object_hash();
+        let mut found = Vec::new();
+
+        for (i, dir) in objects_dirs.iter().enumerate() {
+            let Ok(entries) = std::fs::read_dir(dir.join("pack")) else { continue };
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.extension().is_some_and(|e| e == "idx") {
+                    let modified = entry.metadata().and_then(|m| m.modified()).ok();
+                    found.push((i > 0, modified, path));
+                }
+            }
+        }
+
+        // git consults its own packs before borrowed ones, and the most
+        // recently written first
+        found.sort_by_key(|(borrowed, modified, _)| (*borrowed, std::cmp::Reverse(*modified)));
+
+        let mut packs = Vec::new();
+        for (_, _, idx_path) in found {
+            let pack_path = idx_path.with_extension("pack");
+            let Ok(meta) = std::fs::metadata(&pack_path) else { continue };
+            let index = pack::index::File::at(&idx_path, hash_kind)
+                .with_context(|| format!("Failed to read pack index {}", idx_path.display()))?;
+            let data = pack::data::File::at(&pack_path, hash_kind)
+                .with_context(|| format!("Failed to read pack {}", pack_path.display()))?;
+            let mut offsets: Vec<_> = index
+                .iter()
+                .enumerate()
+                .map(|(i, entry)| (entry.pack_offset, i as pack::index::EntryIndex))
+                .collect();
+            offsets.sort_unstable();
+            let end = meta.len().saturating_sub(hash_kind.len_in_bytes() as u64);
+            packs.push(Pack { index, data, offsets, end });
+        }
+
+        Ok(Packs { objects_dirs, packs })
+    }
+
+    fn find(&self, id: &oid) -> Option<(&Pack, pack::data::Offset)> {
+        self.packs.iter().find_map(|pack| {
+            let entry = pack.index.lookup(id)?;
+            Some((pack, pack.index.pack_offset_at_index(entry)))
+        })
+    }
+
+    fn disk_size(&self, id: &oid) -> Option<u64> {
+        if let Some((pack, offset)) = self.find(id) {
+            let pos = pack.locate(offset)?;
+            let next = pack.offsets.get(pos + 1).map(|&(o, _)| o).unwrap_or(pack.end);
+            return Some(next - offset);
+        }
+
+        let hex = id.to_string();
+        self.objects_dirs
+            .iter()
+            .find_map(|dir| std::fs::metadata(dir.join(&hex[..2]).join(&hex[2..])).ok())
+            .map(|m| m.len())
+    }
+
+    // Loose objects and whole pack entries have no delta base.
+    fn delta_base(&self, id: &oid) -> Option<ObjectId> {
+        let (pack, offset) = self.find(id)?;
+        match pack.data.entry(offset).ok()?.header {
+            pack::data::entry::Header::RefDelta { base_id } => Some(base_id),
+            pack::data::entry::Header::OfsDelta { base_distance } => {
+                let pos = pack.locate(offset.checked_sub(base_distance)?)?;
+                Some(pack.index.oid_at_index(pack.offsets[pos].1).to_owned())
+            }
+            _ => None,
+        }
+    }
+}
@@ -1,163 +1,1833 @@
-use crate::git::GitOps;
-use anyhow::Result;
+use crate::cache::BranchCache;
+use crate::error::Result;
+use crate::git::{
+    BlobChange, BranchRef, CommitBlobs, CommitInfo, GitOps, ObjectKind, Oid, RefKind, UnmergedObject, UnmergedObjects,
+};
+use crate::lfs::{self, LfsStore};
+use crate::refs::{self, RefFilter};
 use rayon::prelude::*;
 use rustc_hash::{FxHashMap, FxHashSet};
-use std::path::Path;
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy)]
 pub struct ObjectInfo {
+    pub kind: ObjectKind,
     pub size: u64,
-    pub branches: FxHashSet<u32>,
+    pub branches: BranchSetId,
+}
+
+pub type BranchSetId = u32;
+
+// Interned, sorted sets of branch indices. Millions of objects typically map
+// onto a few thousand distinct sets, so objects only carry a `BranchSetId`.
+// Id 0 is always the empty set.
+pub struct BranchSets {
+    sets: Vec<Box<[u32]>>,
+    ids: FxHashMap<Box<[u32]>, BranchSetId>,
+    unions: FxHashMap<(BranchSetId, BranchSetId), BranchSetId>,
+}
+
+impl BranchSets {
+    pub fn new() -> Self {
+        let mut sets = BranchSets {
+            sets: Vec::new(),
+            ids: FxHashMap::default(),
+            unions: FxHashMap::default(),
+        };
+        sets.intern(Vec::new());
+        sets
+    }
+
+    pub fn get(&self, id: BranchSetId) -> &[u32] {
+        &self.sets[id as usize]
+    }
+
+    pub fn len(&self) -> usize {
+        self.sets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sets.is_empty()
+    }
+
+    pub fn intern(&mut self, mut branches: Vec<u32>) -> BranchSetId {
+        branches.sort_unstable();
+        branches.dedup();
+        let branches = branches.into_boxed_slice();
+        if let Some(&id) = self.ids.get(&branches) {
+            return id;
+        }
+        let id = self.sets.len() as BranchSetId;
+        self.sets.push(branches.clone());
+        self.ids.insert(branches, id);
+        id
+    }
+
+    pub fn union(&mut self, a: BranchSetId, b: BranchSetId) -> BranchSetId {
+        if a == b || b == 0 {
+            return a;
+        }
+        if a == 0 {
+            return b;
+        }
+        let key = (a.min(b), a.max(b));
+        if let Some(&id) = self.unions.get(&key) {
+            return id;
+        }
+        let merged = self.get(a).iter().chain(self.get(b)).copied().collect();
+        let id = self.intern(merged);
+        self.unions.insert(key, id);
+        id
+    }
+}
+
+impl Default for BranchSets {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct BranchWeight {
     pub branch: String,
+    pub refname: String,
+    pub tip: String,
     pub unique_size: u64,
     pub shared_size: u64,
     pub total_size: u64,
     pub object_count: usize,
     pub unique_count: usize,
     pub shared_count: usize,
+    // Unique size plus an even share of every shared object, so summing it
+    // across branches gives the deduplicated unmerged footprint.
+    pub attributed_size: u64,
+    pub by_kind: KindBreakdown,
+    pub delta: Option<DeltaStats>,
+    pub paths: Option<PathBreakdown>,
+    pub lfs: Option<LfsStats>,
+    pub last_commit: Option<CommitInfo>,
+    // Present when the branch was grouped with its upstream.
+    pub upstream: Option<Upstream>,
 }
 
-pub fn analyze_branches<G: GitOps>(
-    git: &G,
-    repo_path: &Path,
-    default_branch: &str,
-) -> Result<Vec<BranchWeight>> {
-    let branches = git.get_branches(repo_path)?;
-    let branch_count = branches.len();
-    println!("Found {} branches to analyze", branch_count);
+// The remote-tracking branch folded into a local one. `ahead` and `behind`
+// count unmerged commits only the local branch, or only the upstream, has.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Upstream {
+    pub branch: String,
+    pub refname: String,
+    pub tip: String,
+    pub ahead: usize,
+    pub behind: usize,
+}
 
-    if branch_count == 0 {
-        return Ok(Vec::new());
+// Blob sizes (unique + shared) by top-level directory ("vendor/", or
+// "(root)" for files at the top) and by lowercased extension (".psd", or
+// "(none)"), largest first. Present when the path breakdown was requested.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PathBreakdown {
+    pub directories: Vec<(String, KindStats)>,
+    pub extensions: Vec<(String, KindStats)>,
+}
+
+// Content behind the LFS pointer blobs a branch holds (unique + shared),
+// present when LFS pointers were looked for. This is on top of the git object
+// sizes, which only count the pointers themselves. `local_size` is what the
+// local LFS store holds of it; `missing_count` pointers were never fetched.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LfsStats {
+    pub size: u64,
+    pub local_size: u64,
+    pub count: usize,
+    pub missing_count: usize,
+}
+
+impl LfsStats {
+    fn add(&mut self, other: &LfsStats) {
+        self.size += other.size;
+        self.local_size += other.local_size;
+        self.count += other.count;
+        self.missing_count += other.missing_count;
     }
+}
 
-    let branch_names: Vec<String> = branches.iter().map(|(name, _)| name.clone()).collect();
+// Selected object types only, present when delta info was collected.
+// `standalone_size` estimates a pack holding just this branch: objects whose
+// delta base the branch does not reach (typically a base on the default
+// branch) count at their raw size, which is an upper bound.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DeltaStats {
+    pub raw_size: u64,
+    pub standalone_size: u64,
+    pub external_delta_count: usize,
+}
 
-    println!("Collecting unmerged objects from branches...");
+#[derive(Debug, Clone, Copy, Default)]
+struct DeltaGroup {
+    disk: u64,
+    raw: u64,
+    count: usize,
+}
 
-    let partial_maps: Vec<(u32, FxHashMap<String, u64>)> = branches
-        .par_iter()
-        .enumerate()
-        .filter_map(|(i, (_, tip))| {
-            if i % 100 == 0 {
-                eprintln!("Processing branch {}/{}", i, branch_count);
+// Keyed by object type, the object's branch set and its delta base's branch
+// set. Objects stored whole use their own set as the base set.
+type DeltaGroups = FxHashMap<(ObjectKind, BranchSetId, BranchSetId), DeltaGroup>;
+
+// Raw size and delta base of objects that came from the cache, not the walk.
+type CachedDeltas = FxHashMap<Oid, (u64, Option<Oid>)>;
+
+const ROOT_LABEL: &str = "(root)";
+const NO_EXTENSION_LABEL: &str = "(none)";
+const UNKNOWN_LABEL: &str = "(unknown)";
+
+// Interned directory and extension labels of every unmerged blob, so the
+// breakdown keeps no string per object.
+#[derive(Default)]
+struct PathLabels {
+    names: Vec<String>,
+    ids: FxHashMap<String, u32>,
+    // (directory, extension) per blob
+    blobs: FxHashMap<Oid, (u32, u32)>,
+}
+
+impl PathLabels {
+    fn record(&mut self, object: &UnmergedObject, path: Option<&str>) {
+        if object.kind != ObjectKind::Blob || self.blobs.contains_key(&object.oid) {
+            return;
+        }
+        let directory = self.intern(path.map_or(UNKNOWN_LABEL, top_directory));
+        let extension = self.intern(&path.map_or(Cow::Borrowed(UNKNOWN_LABEL), extension));
+        self.blobs.insert(object.oid, (directory, extension));
+    }
+
+    fn intern(&mut self, label: &str) -> u32 {
+        if let Some(&id) = self.ids.get(label) {
+            return id;
+        }
+        let id = self.names.len() as u32;
+        self.names.push(label.to_string());
+        self.ids.insert(label.to_string(), id);
+        id
+    }
+}
+
+// `vendor/lib/x.c` -> `vendor/`
+fn top_directory(path: &str) -> &str {
+    match path.find('/') {
+        Some(slash) => &path[..=slash],
+        None => ROOT_LABEL,
+    }
+}
+
+// `Logo.PSD` -> `.psd`; dot files like `.gitignore` have no extension.
+fn extension(path: &str) -> Cow<'_, str> {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    match name.rfind('.') {
+        Some(dot) if dot > 0 && dot + 1 < name.len() => {
+            let extension = &name[dot..];
+            if extension.bytes().any(|b| b.is_ascii_uppercase()) {
+                Cow::Owned(extension.to_ascii_lowercase())
+            } else {
+                Cow::Borrowed(extension)
             }
+        }
+        _ => Cow::Borrowed(NO_EXTENSION_LABEL),
+    }
+}
 
-            git.get_unmerged_blobs(repo_path, tip, default_branch)
-                .ok()
-                .filter(|m| !m.is_empty())
-                .map(|m| {
-                    let fx_map: FxHashMap<String, u64> = m.into_iter().collect();
-                    (i as u32, fx_map)
-                })
-        })
+impl BranchWeight {
+    pub fn age_days(&self, now: i64) -> Option<u64> {
+        self.last_commit.as_ref().map(|info| info.age_days(now))
+    }
+
+    // See `SortKey::Score`; zero when the tip's date is unknown.
+    pub fn score(&self, now: i64) -> f64 {
+        let mb = self.attributed_size as f64 / (1024.0 * 1024.0);
+        mb * self.age_days(now).unwrap_or(0) as f64
+    }
+
+    pub fn kind(&self) -> RefKind {
+        RefKind::of(&self.refname)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct KindStats {
+    pub size: u64,
+    pub count: usize,
+}
+
+// Unique + shared totals per object type, independent of which types were
+// selected to feed `total_size`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct KindBreakdown {
+    pub commits: KindStats,
+    pub trees: KindStats,
+    pub blobs: KindStats,
+    pub tags: KindStats,
+}
+
+impl KindBreakdown {
+    pub fn get(&self, kind: ObjectKind) -> &KindStats {
+        match kind {
+            ObjectKind::Commit => &self.commits,
+            ObjectKind::Tree => &self.trees,
+            ObjectKind::Blob => &self.blobs,
+            ObjectKind::Tag => &self.tags,
+        }
+    }
+
+    fn get_mut(&mut self, kind: ObjectKind) -> &mut KindStats {
+        match kind {
+            ObjectKind::Commit => &mut self.commits,
+            ObjectKind::Tree => &mut self.trees,
+            ObjectKind::Blob => &mut self.blobs,
+            ObjectKind::Tag => &mut self.tags,
+        }
+    }
+}
+
+// What `analyze_branches` scans. Without any base the default branch
+// (master, then main) is used.
+#[derive(Debug, Clone)]
+pub struct AnalysisOptions {
+    bases: Vec<String>,
+    object_types: Vec<ObjectKind>,
+    delta_info: bool,
+    path_breakdown: bool,
+    lfs: bool,
+    cache_dir: Option<PathBuf>,
+    older_than: Option<Duration>,
+    newer_than: Option<Duration>,
+    sort_by: SortKey,
+    as_of: Option<i64>,
+    refs: RefFilter,
+    group_upstreams: bool,
+}
+
+impl Default for AnalysisOptions {
+    fn default() -> Self {
+        AnalysisOptions {
+            bases: Vec::new(),
+            object_types: vec![ObjectKind::Blob],
+            delta_info: false,
+            path_breakdown: false,
+            lfs: false,
+            cache_dir: None,
+            older_than: None,
+            newer_than: None,
+            sort_by: SortKey::Total,
+            as_of: None,
+            refs: RefFilter::default(),
+            group_upstreams: false,
+        }
+    }
+}
+
+impl AnalysisOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // A branch name, refname or glob, see `refs::resolve_bases`.
+    pub fn base(mut self, pattern: impl Into<String>) -> Self {
+        self.bases.push(pattern.into());
+        self
+    }
+
+    pub fn bases<I, S>(mut self, patterns: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.bases.extend(patterns.into_iter().map(Into::into));
+        self
+    }
+
+    // Only scan branches matching one of these base-style globs.
+    pub fn include(mut self, pattern: impl Into<String>) -> Self {
+        self.refs.include.push(pattern.into());
+        self
+    }
+
+    // Never scan branches matching this glob, even if included.
+    pub fn exclude(mut self, pattern: impl Into<String>) -> Self {
+        self.refs.exclude.push(pattern.into());
+        self
+    }
+
+    // Only scan remote-tracking branches of this remote.
+    pub fn remote(mut self, name: impl Into<String>) -> Self {
+        self.refs.remote = Some(name.into());
+        self
+    }
+
+    // Types counted in the sizes; the per-type breakdown always has all four.
+    pub fn object_types(mut self, kinds: impl IntoIterator<Item = ObjectKind>) -> Self {
+        self.object_types = kinds.into_iter().collect();
+        self
+    }
+
+    pub fn delta_info(mut self, enabled: bool) -> Self {
+        self.delta_info = enabled;
+        self
+    }
+
+    // Blob sizes by directory and extension, per branch and across all of
+    // them; see `PathBreakdown`.
+    pub fn path_breakdown(mut self, enabled: bool) -> Self {
+        self.path_breakdown = enabled;
+        self
+    }
+
+    // Reads blobs small enough to be LFS pointers and weighs the content
+    // they point at; see `LfsStats`.
+    pub fn lfs(mut self, enabled: bool) -> Self {
+        self.lfs = enabled;
+        self
+    }
+
+    // Reuse per-branch results for tips scanned against the same base tips
+    // before; only branches that moved are walked.
+    pub fn cache_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.cache_dir = Some(dir.into());
+        self
+    }
+
+    // Only weigh branches whose tip was committed at least this long ago.
+    // The others are still walked, so objects they share keep counting as
+    // shared, but are skipped.
+    pub fn older_than(mut self, age: Duration) -> Self {
+        self.older_than = Some(age);
+        self
+    }
+
+    // Only weigh branches whose tip was committed less than this long ago.
+    pub fn newer_than(mut self, age: Duration) -> Self {
+        self.newer_than = Some(age);
+        self
+    }
+
+    // Report a local branch and the remote-tracking branch it follows as one
+    // entry, instead of each making the other's objects shared.
+    pub fn group_upstreams(mut self, enabled: bool) -> Self {
+        self.group_upstreams = enabled;
+        self
+    }
+
+    pub fn sort_by(mut self, key: SortKey) -> Self {
+        self.sort_by = key;
+        self
+    }
+
+    // Unix time ages are measured from; the current time by default.
+    pub fn as_of(mut self, timestamp: i64) -> Self {
+        self.as_of = Some(timestamp);
+        self
+    }
+
+    // Why a branch last committed at `date` falls outside the age limits. A
+    // branch whose date could not be read never passes them.
+    fn age_filter(&self, date: Option<i64>, now: i64) -> Option<String> {
+        if self.older_than.is_none() && self.newer_than.is_none() {
+            return None;
+        }
+        let Some(date) = date else { return Some("unknown tip date".to_string()) };
+        let age = (now - date).max(0) as u64;
+        let days = |d: Duration| d.as_secs() as f64 / 86400.0;
+        match (self.older_than, self.newer_than) {
+            (Some(limit), _) if age < limit.as_secs() => {
+                Some(format!("last commit {} days ago, not older than {} days", age / 86400, days(limit)))
+            }
+            (_, Some(limit)) if age >= limit.as_secs() => {
+                Some(format!("last commit {} days ago, not newer than {} days", age / 86400, days(limit)))
+            }
+            _ => None,
+        }
+    }
+}
+
+// Order of `Analysis::weights`. `Score` ranks big and stale branches first:
+// attributed size in MB times days since the last commit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    Total,
+    Unique,
+    Age,
+    Score,
+}
+
+impl SortKey {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "total" => Some(SortKey::Total),
+            "unique" => Some(SortKey::Unique),
+            "age" => Some(SortKey::Age),
+            "score" => Some(SortKey::Score),
+            _ => None,
+        }
+    }
+}
+
+// Result of the shared walk. Per-set totals of the selected object types stay
+// around so set-level questions ("what do we get back if we delete these?")
+// can be answered without walking the repository again.
+pub struct Analysis {
+    // Resolved base refs the branches were compared against.
+    pub bases: Vec<String>,
+    pub object_types: Vec<ObjectKind>,
+    pub branches: Vec<BranchRef>,
+    // Parallel to `branches`.
+    pub statuses: Vec<BranchStatus>,
+    pub branch_sets: BranchSets,
+    // In the order of `AnalysisOptions::sort_by`.
+    pub weights: Vec<BranchWeight>,
+    // Present when a cache directory was used.
+    pub cache: Option<CacheStats>,
+    // All unmerged blobs, each counted once, when the path breakdown was
+    // requested.
+    pub paths: Option<PathBreakdown>,
+    // All LFS content of unmerged branches, each pointer counted once.
+    pub lfs: Option<LfsStats>,
+    // Unix time branch ages were measured from.
+    pub as_of: i64,
+    set_totals: Vec<KindStats>,
+    // Branch set of every unmerged object, for questions about single objects.
+    object_map: FxHashMap<Oid, ObjectInfo>,
+    // Local branch each upstream was grouped with.
+    grouped_into: FxHashMap<u32, u32>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CacheStats {
+    pub hits: usize,
+    pub misses: usize,
+}
+
+// Only `Ok` branches with unmerged objects of the selected types end up in
+// `weights`; the others say why they are missing.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BranchStatus {
+    Ok,
+    Failed(String),
+    Skipped(String),
+}
+
+impl BranchStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BranchStatus::Ok => "ok",
+            BranchStatus::Failed(_) => "failed",
+            BranchStatus::Skipped(_) => "skipped",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BranchError {
+    pub branch: String,
+    pub refname: String,
+    // "scan" or "details"
+    pub stage: &'static str,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reclaim {
+    pub size: u64,
+    pub count: usize,
+    // Bytes that would stay behind if only this branch were kept out of the
+    // set, i.e. reclaimable(set) - reclaimable(set without branch).
+    pub marginal: Vec<(u32, u64)>,
+}
+
+impl Analysis {
+    pub fn count(&self, status: &str) -> usize {
+        self.statuses.iter().filter(|s| s.as_str() == status).count()
+    }
+
+    pub fn failures(&self) -> Vec<BranchError> {
+        self.branches
+            .iter()
+            .zip(&self.statuses)
+            .filter_map(|(branch, status)| match status {
+                BranchStatus::Failed(reason) => Some(BranchError {
+                    branch: branch.name.clone(),
+                    refname: branch.refname.clone(),
+                    stage: "scan",
+                    reason: reason.clone(),
+                }),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // Every unmerged object of the selected types counted once, and the part
+    // of it more than one branch reaches. Unlike sums over `weights`, this
+    // still covers objects of branches left out of the reports.
+    pub fn unmerged_size(&self) -> (u64, u64) {
+        let mut total = 0;
+        let mut shared = 0;
+        for (set_id, totals) in self.set_totals.iter().enumerate() {
+            total += totals.size;
+            if self.branch_sets.get(set_id as BranchSetId).len() > 1 {
+                shared += totals.size;
+            }
+        }
+        (total, shared)
+    }
+
+    // Indices of the branches reaching `oid`; empty for merged objects.
+    pub fn branches_holding(&self, oid: &Oid) -> &[u32] {
+        self.object_map.get(oid).map_or(&[], |info| self.branch_sets.get(info.branches))
+    }
+
+    // Accepts the short name or the full refname. A grouped upstream resolves
+    // to its local branch, whose entry holds the objects of both.
+    pub fn branch_index(&self, name: &str) -> Option<u32> {
+        let index = self
+            .branches
+            .iter()
+            .position(|b| b.refname == name)
+            .or_else(|| self.branches.iter().position(|b| b.name == name))? as u32;
+        Some(self.grouped_into.get(&index).copied().unwrap_or(index))
+    }
+
+    // Objects of the selected types that only the given branches reach, so
+    // deleting exactly this set of branches makes them unreachable.
+    pub fn reclaimable_if_deleted(&self, branches: &[u32]) -> Reclaim {
+        let mut in_set = vec![false; self.branches.len()];
+        for &b in branches {
+            in_set[b as usize] = true;
+        }
+
+        let mut size = 0;
+        let mut count = 0;
+        let mut marginal: FxHashMap<u32, u64> = branches.iter().map(|&b| (b, 0)).collect();
+
+        for (set_id, totals) in self.set_totals.iter().enumerate() {
+            if totals.count == 0 {
+                continue;
+            }
+            let members = self.branch_sets.get(set_id as BranchSetId);
+            if !members.iter().all(|&m| in_set[m as usize]) {
+                continue;
+            }
+            size += totals.size;
+            count += totals.count;
+            for m in members {
+                *marginal.entry(*m).or_default() += totals.size;
+            }
+        }
+
+        let mut marginal: Vec<(u32, u64)> = marginal.into_iter().collect();
+        marginal.sort_by_key(|&(b, size)| (std::cmp::Reverse(size), b));
+
+        Reclaim { size, count, marginal }
+    }
+
+    // Greedy cleanup plan: repeatedly delete the branch that frees the most
+    // bytes given what is already deleted. When no single deletion frees
+    // anything more, the branch holding the largest fraction of objects that
+    // are still pinned by several branches is taken, so shared objects get
+    // freed over the next steps instead of stalling the plan. Skipped
+    // branches, e.g. ones outside the age limits, are never picked.
+    pub fn plan_cleanup(&self, goal: PlanGoal) -> Vec<PlanStep> {
+        let branch_count = self.branches.len();
+        let mut sets_of: Vec<Vec<usize>> = vec![Vec::new(); branch_count];
+        let mut remaining: Vec<usize> = vec![0; self.set_totals.len()];
+        let mut gain: Vec<u64> = vec![0; branch_count];
+        let mut share: Vec<f64> = vec![0.0; branch_count];
+
+        for (set_id, totals) in self.set_totals.iter().enumerate() {
+            if totals.size == 0 {
+                continue;
+            }
+            let members = self.branch_sets.get(set_id as BranchSetId);
+            remaining[set_id] = members.len();
+            for &m in members {
+                sets_of[m as usize].push(set_id);
+                share[m as usize] += totals.size as f64 / members.len() as f64;
+                if members.len() == 1 {
+                    gain[m as usize] += totals.size;
+                }
+            }
+        }
+
+        let mut deleted = vec![false; branch_count];
+        let mut steps: Vec<PlanStep> = Vec::new();
+        let mut cumulative = 0u64;
+
+        loop {
+            match goal {
+                PlanGoal::TargetBytes(target) if cumulative >= target => break,
+                PlanGoal::TopK(k) if steps.len() >= k => break,
+                _ => {}
+            }
+
+            let best = (0..branch_count)
+                .filter(|&b| !deleted[b] && share[b] > 0.0 && self.statuses[b] == BranchStatus::Ok)
+                .max_by(|&a, &b| {
+                    gain[a]
+                        .cmp(&gain[b])
+                        .then(share[a].total_cmp(&share[b]))
+                        .then(b.cmp(&a))
+                });
+            let Some(best) = best else { break };
+
+            deleted[best] = true;
+            let freed = gain[best];
+            cumulative += freed;
+            steps.push(PlanStep { branch: best as u32, reclaimed: freed, cumulative });
+
+            for &set_id in &sets_of[best] {
+                let size = self.set_totals[set_id].size;
+                let before = remaining[set_id];
+                let after = before - 1;
+                remaining[set_id] = after;
+
+                for &m in self.branch_sets.get(set_id as BranchSetId) {
+                    let m = m as usize;
+                    if deleted[m] {
+                        continue;
+                    }
+                    share[m] += size as f64 / after as f64 - size as f64 / before as f64;
+                    if after == 1 {
+                        gain[m] += size;
+                    }
+                }
+            }
+        }
+
+        steps
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlanGoal {
+    TargetBytes(u64),
+    TopK(usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlanStep {
+    pub branch: u32,
+    pub reclaimed: u64,
+    pub cumulative: u64,
+}
+
+pub fn analyze_branches<G: GitOps>(git: &G, repo_path: &Path, options: &AnalysisOptions) -> Result<Analysis> {
+    let bases = if options.bases.is_empty() {
+        vec![git.detect_default_branch(repo_path)?]
+    } else {
+        refs::resolve_bases(git, repo_path, &options.bases)?
+    };
+    println!("Base branches: {}", bases.join(", "));
+    let object_types = options.object_types.as_slice();
+    let delta_info = options.delta_info;
+    let mut labels = options.path_breakdown.then(PathLabels::default);
+
+    let (mut branches, mut broken) = git.get_branches(repo_path, &bases)?;
+    if !options.refs.is_empty() {
+        for pattern in &options.refs.include {
+            let single = RefFilter { include: vec![pattern.clone()], ..RefFilter::default() };
+            if !branches.iter().any(|b| single.matches(&b.refname)) {
+                println!("Warning: include pattern '{}' matched no unmerged branches", pattern);
+            }
+        }
+        let found = branches.len() + broken.len();
+        branches.retain(|b| options.refs.matches(&b.refname));
+        broken.retain(|b| options.refs.matches(&b.refname));
+        println!("Ignoring {} branches outside the ref filters", found - branches.len() - broken.len());
+    }
+    println!("Found {} branches to analyze", branches.len());
+
+    let mut statuses = vec![BranchStatus::Ok; branches.len()];
+    for broken in broken {
+        println!("Warning: cannot read {}: {}", broken.refname, broken.reason);
+        branches.push(BranchRef::new(broken.refname, String::new()));
+        statuses.push(BranchStatus::Failed(broken.reason));
+    }
+    let branch_count = branches.len();
+
+    let mut object_map: FxHashMap<Oid, ObjectInfo> = FxHashMap::default();
+    let mut branch_sets = BranchSets::new();
+    let mut cached_deltas = CachedDeltas::default();
+
+    let cache = match &options.cache_dir {
+        Some(dir) => open_cache(git, repo_path, dir, &bases),
+        None => None,
+    };
+    let cached = match &cache {
+        Some(cache) => load_cached(
+            cache,
+            &branches,
+            &statuses,
+            &mut object_map,
+            &mut branch_sets,
+            delta_info.then_some(&mut cached_deltas),
+            labels.as_mut(),
+        ),
+        None => vec![false; branch_count],
+    };
+    let scanned: Vec<bool> = statuses
+        .iter()
+        .zip(&cached)
+        .map(|(status, &cached)| *status == BranchStatus::Ok && !cached)
         .collect();
+    let cache_stats = cache.as_ref().map(|_| CacheStats {
+        hits: cached.iter().filter(|&&c| c).count(),
+        misses: scanned.iter().filter(|&&s| s).count(),
+    });
+
+    let mut objects = collect_objects(git, repo_path, &branches, &mut statuses, &cached, &bases, delta_info)?;
+
+    println!("Attributing {} unmerged objects to branches...", objects.len());
+    attribute_objects(&branches, &objects, &mut object_map, &mut branch_sets);
+    objects.release_edges();
+    if let Some(labels) = &mut labels {
+        for (node, object) in objects.iter().enumerate() {
+            labels.record(object, objects.path(node));
+        }
+    }
+    if let (Some(cache), Some(stats)) = (&cache, &cache_stats) {
+        // branches that failed during the walk are not worth remembering
+        let scanned: Vec<bool> = scanned
+            .iter()
+            .zip(&statuses)
+            .map(|(&scanned, status)| scanned && *status == BranchStatus::Ok)
+            .collect();
+        store_scanned(cache, &branches, &scanned, &objects, &object_map, &branch_sets, delta_info);
+        // every ref counts, including the ones ref filters or other bases
+        // leave out, so a narrower run does not cost the next one its hits
+        match git.list_refs(repo_path) {
+            Ok(refs) => {
+                let failed: FxHashSet<&str> = branches
+                    .iter()
+                    .zip(&statuses)
+                    .filter(|(_, status)| matches!(status, BranchStatus::Failed(_)))
+                    .map(|(b, _)| b.tip.as_str())
+                    .collect();
+                let live: FxHashSet<&str> =
+                    refs.iter().map(|(_, tip)| tip.as_str()).filter(|tip| !failed.contains(tip)).collect();
+                cache.prune(&live);
+            }
+            Err(err) => println!("Warning: cache not pruned: {}", err),
+        }
+        println!("Cache: {} hits, {} misses ({})", stats.hits, stats.misses, cache.dir().display());
+    }
+    // the labels and the cache have what they need of the paths
+    objects.release_paths();
+
+    // after the cache is written, which keeps every branch's own objects
+    let groups = if options.group_upstreams { upstream_groups(&branches, &statuses) } else { Vec::new() };
+    let divergence = divergence(&groups, branch_count, &object_map, &branch_sets);
+    if !groups.is_empty() {
+        println!("Grouping {} local branches with their upstream", groups.len());
+        merge_upstreams(&groups, branch_count, &mut object_map, &mut branch_sets);
+        for &(local, upstream) in &groups {
+            statuses[upstream] = BranchStatus::Skipped(format!("grouped with {}", branches[local].name));
+        }
+    }
 
-    println!("Merging {} branch results...", partial_maps.len());
-    let object_map = merge_branch_objects(partial_maps);
+    let delta_groups = if delta_info {
+        group_deltas(&objects, cached_deltas, &object_map)
+    } else {
+        DeltaGroups::default()
+    };
+    drop(objects);
+
+    println!(
+        "Interned {} distinct branch sets for {} objects",
+        branch_sets.len(),
+        object_map.len()
+    );
 
     println!("Calculating branch weights...");
-    let results = calculate_weights(&branch_names, &object_map);
+    let per_set = sum_per_set(&object_map, &branch_sets);
+    let delta = delta_info.then(|| delta_stats(branch_count, &delta_groups, &branch_sets, object_types));
+    let (per_branch_paths, paths) = match &labels {
+        Some(labels) => {
+            let (per_branch, rollup) = path_breakdowns(branch_count, labels, &object_map, &branch_sets);
+            (Some(per_branch), Some(rollup))
+        }
+        None => (None, None),
+    };
+    let (per_branch_lfs, lfs) = if options.lfs {
+        lfs_stats(git, repo_path, branch_count, &object_map, &branch_sets).unzip()
+    } else {
+        (None, None)
+    };
+    let mut results = calculate_weights(
+        &branches,
+        &per_set,
+        &branch_sets,
+        object_types,
+        delta.as_deref(),
+        per_branch_paths.as_deref(),
+        per_branch_lfs.as_deref(),
+    );
 
     println!("Found {} branches with unmerged objects", results.len());
 
-    Ok(results)
+    for (&(local, upstream), &(ahead, behind)) in groups.iter().zip(&divergence) {
+        let Some(weight) = results.iter_mut().find(|w| w.refname == branches[local].refname) else { continue };
+        weight.upstream = Some(Upstream {
+            branch: branches[upstream].name.clone(),
+            refname: branches[upstream].refname.clone(),
+            tip: branches[upstream].tip.clone(),
+            ahead,
+            behind,
+        });
+    }
+
+    let now = options.as_of.unwrap_or_else(unix_now);
+    for (branch, status) in branches.iter().zip(statuses.iter_mut()) {
+        let reason = options.age_filter(branch.last_commit.as_ref().map(|info| info.date), now);
+        if let (BranchStatus::Ok, Some(reason)) = (&status, reason) {
+            *status = BranchStatus::Skipped(reason);
+        }
+    }
+    let in_range: FxHashSet<&str> = branches
+        .iter()
+        .zip(&statuses)
+        .filter(|(_, status)| **status == BranchStatus::Ok)
+        .map(|(b, _)| b.refname.as_str())
+        .collect();
+    results.retain(|w| in_range.contains(w.refname.as_str()));
+    sort_weights(&mut results, options.sort_by, now);
+
+    let weighed: FxHashSet<&str> = results.iter().map(|w| w.refname.as_str()).collect();
+    for (branch, status) in branches.iter().zip(statuses.iter_mut()) {
+        if *status == BranchStatus::Ok && !weighed.contains(branch.refname.as_str()) {
+            *status = BranchStatus::Skipped("no unmerged objects of the selected types".to_string());
+        }
+    }
+
+    let set_totals = per_set
+        .iter()
+        .map(|breakdown| {
+            object_types.iter().fold(KindStats::default(), |acc, &kind| KindStats {
+                size: acc.size + breakdown.get(kind).size,
+                count: acc.count + breakdown.get(kind).count,
+            })
+        })
+        .collect();
+
+    Ok(Analysis {
+        bases,
+        object_types: object_types.to_vec(),
+        branches,
+        statuses,
+        branch_sets,
+        weights: results,
+        cache: cache_stats,
+        paths,
+        lfs,
+        as_of: now,
+        set_totals,
+        object_map,
+        grouped_into: groups.iter().map(|&(local, upstream)| (upstream as u32, local as u32)).collect(),
+    })
 }
 
-#[derive(Debug, Clone)]
+// Weights come sorted by total size; other keys re-sort stably, so ties keep
+// that order. Oldest first for `Age`, largest first otherwise.
+fn sort_weights(weights: &mut [BranchWeight], key: SortKey, now: i64) {
+    match key {
+        SortKey::Total => {}
+        SortKey::Unique => weights.sort_by_key(|w| std::cmp::Reverse(w.unique_size)),
+        SortKey::Age => weights.sort_by_key(|w| std::cmp::Reverse(w.age_days(now))),
+        SortKey::Score => weights.sort_by(|a, b| b.score(now).total_cmp(&a.score(now))),
+    }
+}
+
+fn unix_now() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64)
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct CommitWeight {
     pub commit: String,
+    // Selected types only, first introduced by this commit.
     pub size: u64,
+    pub object_count: usize,
+    pub readded: Vec<ReaddedBlob>,
+}
+
+// A blob a commit adds although it already existed, earlier on the branch
+// (`first_commit`) or on a base (`None`), as after a revert. It adds nothing
+// to the branch's size.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReaddedBlob {
+    pub blob: String,
+    pub size: u64,
+    pub first_commit: Option<String>,
+}
+
+// One of the biggest blobs of a branch. `shared` when other branches reach
+// it too.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileWeight {
+    pub blob: String,
+    pub path: Option<String>,
+    pub size: u64,
+    pub commit: String,
+    pub shared: bool,
 }
 
 #[derive(Debug, Clone)]
 pub struct BranchDetail {
     pub branch: String,
+    pub refname: String,
+    pub last_commit: Option<CommitInfo>,
+    // The analysis' `as_of`, so ages match the branch reports.
+    pub as_of: i64,
     pub total_size: u64,
+    // Parents before children.
     pub commits: Vec<CommitWeight>,
+    // Largest first.
+    pub top_files: Vec<FileWeight>,
 }
 
+// Branches whose commits cannot be read are returned as errors rather than
+// left out of the details.
 pub fn analyze_branch_details<G: GitOps>(
     git: &G,
     repo_path: &Path,
-    branches: &[BranchWeight],
-    default_branch: &str,
+    analysis: &Analysis,
     top_n: usize,
-) -> Result<Vec<BranchDetail>> {
-    let top_branches: Vec<_> = branches.iter().take(top_n).collect();
+    files_per_branch: usize,
+) -> Result<(Vec<BranchDetail>, Vec<BranchError>)> {
+    let top_branches: Vec<_> = analysis.weights.iter().take(top_n).collect();
+    let bases = &analysis.bases;
 
-    let details: Vec<BranchDetail> = top_branches
+    let outcomes: Vec<Result<BranchDetail, BranchError>> = top_branches
         .par_iter()
-        .filter_map(|bw| {
-            let branch_ref = format!("refs/remotes/{}", bw.branch);
-            let commits = git.get_unmerged_commits(repo_path, &branch_ref, default_branch).ok()?;
-
-            let commit_weights: Vec<CommitWeight> = commits
-                .into_iter()
-                .map(|cb| {
-                    let size: u64 = cb.blobs.values().sum();
-                    CommitWeight { commit: cb.commit, size }
-                })
-                .filter(|cw| cw.size > 0)
-                .collect();
-
-            let total: u64 = commit_weights.iter().map(|c| c.size).sum();
+        .map(|bw| {
+            let failed = |err: crate::error::BranchWeightError| BranchError {
+                branch: bw.branch.clone(),
+                refname: bw.refname.clone(),
+                stage: "details",
+                reason: format!("{:#}", err),
+            };
+            // the tips that were scanned, even if the refs have moved since;
+            // a grouped upstream's own commits count towards the entry too
+            let mut tips = vec![bw.tip.clone()];
+            tips.extend(bw.upstream.as_ref().map(|upstream| upstream.tip.clone()));
+            let objects = git.get_unmerged_objects(repo_path, &tips, bases, false).map_err(failed)?;
+            let mut diffs = git.get_unmerged_commits(repo_path, &bw.tip, bases).map_err(failed)?;
+            if let Some(upstream) = &bw.upstream {
+                let exclude: Vec<String> = bases.iter().chain(std::iter::once(&bw.tip)).cloned().collect();
+                diffs.extend(git.get_unmerged_commits(repo_path, &upstream.tip, &exclude).map_err(failed)?);
+            }
 
-            Some(BranchDetail {
+            let (commits, introduced_by) = attribute_commits(&tips, &objects, &diffs, &analysis.object_types);
+            let top_files = largest_blobs(analysis, &objects, &introduced_by, files_per_branch);
+            Ok(BranchDetail {
                 branch: bw.branch.clone(),
-                total_size: total,
-                commits: commit_weights,
+                refname: bw.refname.clone(),
+                last_commit: bw.last_commit.clone(),
+                as_of: analysis.as_of,
+                total_size: commits.iter().map(|c| c.size).sum(),
+                commits,
+                top_files,
             })
         })
         .collect();
 
-    Ok(details)
+    let mut details = Vec::new();
+    let mut errors = Vec::new();
+    for outcome in outcomes {
+        match outcome {
+            Ok(detail) => details.push(detail),
+            Err(error) => errors.push(error),
+        }
+    }
+
+    Ok((details, errors))
 }
 
-fn merge_branch_objects(
-    partial_maps: Vec<(u32, FxHashMap<String, u64>)>,
-) -> FxHashMap<String, ObjectInfo> {
-    let mut object_map: FxHashMap<String, ObjectInfo> = FxHashMap::default();
+// Credits every unmerged object of a branch to the first commit, parents
+// before children, that reaches it: the commit object itself plus whatever
+// its tree holds that no earlier commit's tree did. Each object is counted
+// once, so the commits add up to the branch's size. Blobs in a commit's diff
+// that were credited elsewhere are reported as re-added. Also returns, per
+// object, the index in `objects` of the commit it was credited to. Commits
+// only a later tip reaches come after those of the earlier ones.
+fn attribute_commits(
+    tips: &[String],
+    objects: &UnmergedObjects,
+    diffs: &[CommitBlobs],
+    object_types: &[ObjectKind],
+) -> (Vec<CommitWeight>, Vec<Option<usize>>) {
+    let index = objects.index();
+    let tips: Vec<usize> = tips
+        .iter()
+        .filter_map(|tip| Oid::from_hex(tip).and_then(|tip| index.get(&tip)).map(|&tip| tip as usize))
+        .collect();
+    let order = commits_parents_first(objects, &tips);
 
-    for (branch_idx, branch_objects) in partial_maps {
-        for (oid, size) in branch_objects {
-            object_map
-                .entry(oid)
-                .and_modify(|info| {
-                    info.branches.insert(branch_idx);
+    let mut commits: Vec<CommitWeight> = order
+        .iter()
+        .map(|&node| CommitWeight {
+            commit: objects[node].oid.to_string(),
+            size: 0,
+            object_count: 0,
+            readded: Vec::new(),
+        })
+        .collect();
+
+    let mut credited: Vec<Option<usize>> = vec![None; objects.len()];
+    let mut stack = Vec::new();
+    for (pos, &commit) in order.iter().enumerate() {
+        stack.push(commit);
+        while let Some(node) = stack.pop() {
+            if credited[node].is_some() {
+                continue;
+            }
+            credited[node] = Some(pos);
+            let object = &objects[node];
+            if object_types.contains(&object.kind) {
+                commits[pos].size += object.size;
+                commits[pos].object_count += 1;
+            }
+            // parents get their own turn
+            stack.extend(
+                objects
+                    .children(node)
+                    .iter()
+                    .map(|&child| child as usize)
+                    .filter(|&child| objects[child].kind != ObjectKind::Commit),
+            );
+        }
+    }
+
+    for diff in diffs {
+        let commit = Oid::from_hex(&diff.commit).and_then(|oid| index.get(&oid));
+        let Some(pos) = commit.and_then(|&c| credited[c as usize]) else {
+            continue;
+        };
+        let mut readded: Vec<ReaddedBlob> = diff
+            .blobs
+            .iter()
+            .filter_map(|(blob, &size)| {
+                let first = Oid::from_hex(blob).and_then(|oid| index.get(&oid)).and_then(|&b| credited[b as usize]);
+                (first != Some(pos)).then(|| ReaddedBlob {
+                    blob: blob.clone(),
+                    size,
+                    first_commit: first.map(|first| commits[first].commit.clone()),
                 })
-                .or_insert_with(|| {
-                    let mut branches = FxHashSet::default();
-                    branches.insert(branch_idx);
-                    ObjectInfo { size, branches }
-                });
+            })
+            .collect();
+        readded.sort_by(|a, b| a.blob.cmp(&b.blob));
+        commits[pos].readded = readded;
+    }
+
+    let introduced_by = credited.into_iter().map(|pos| pos.map(|pos| order[pos])).collect();
+    commits.retain(|c| c.size > 0 || !c.readded.is_empty());
+    (commits, introduced_by)
+}
+
+// The `limit` biggest blobs of one branch's walk, ties broken by id so the
+// list does not depend on walk order.
+fn largest_blobs(
+    analysis: &Analysis,
+    objects: &UnmergedObjects,
+    introduced_by: &[Option<usize>],
+    limit: usize,
+) -> Vec<FileWeight> {
+    // (node, commit) pairs
+    let mut blobs: Vec<(usize, usize)> = introduced_by
+        .iter()
+        .enumerate()
+        .filter(|&(node, _)| objects[node].kind == ObjectKind::Blob)
+        .filter_map(|(node, commit)| Some((node, (*commit)?)))
+        .collect();
+    blobs.sort_by(|&(a, _), &(b, _)| objects[b].size.cmp(&objects[a].size).then(objects[a].oid.cmp(&objects[b].oid)));
+
+    blobs
+        .into_iter()
+        .take(limit)
+        .map(|(node, commit)| {
+            let object = &objects[node];
+            FileWeight {
+                blob: object.oid.to_string(),
+                path: objects.path(node).map(str::to_string),
+                size: object.size,
+                commit: objects[commit].oid.to_string(),
+                shared: analysis.branches_holding(&object.oid).len() > 1,
+            }
+        })
+        .collect()
+}
+
+// Depth first from each tip in turn, first parent first, emitting a commit
+// once all its unmerged parents are out; for a single tip the order of
+// `rev-list --topo-order --reverse`.
+fn commits_parents_first(objects: &UnmergedObjects, tips: &[usize]) -> Vec<usize> {
+    let mut order = Vec::new();
+    let mut visited = vec![false; objects.len()];
+    let mut stack: Vec<(usize, bool)> = tips.iter().rev().map(|&tip| (tip, false)).collect();
+
+    while let Some((node, expanded)) = stack.pop() {
+        if expanded {
+            order.push(node);
+            continue;
+        }
+        if visited[node] {
+            continue;
+        }
+        visited[node] = true;
+        stack.push((node, true));
+
+        let parents: Vec<usize> = objects
+            .children(node)
+            .iter()
+            .map(|&child| child as usize)
+            .filter(|&child| objects[child].kind == ObjectKind::Commit && !visited[child])
+            .collect();
+        stack.extend(parents.into_iter().rev().map(|parent| (parent, false)));
+    }
+
+    order
+}
+
+// An unmerged blob at or above the size threshold of `find_large_objects`.
+// `commit` is the first commit adding it in ancestry order: the one with the
+// lowest generation, committer time only breaking ties between unrelated
+// lines. It is `None` when only a merge commit brought the blob in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LargeObject {
+    pub blob: String,
+    pub size: u64,
+    // Indices into `Analysis::branches`.
+    pub branches: Vec<u32>,
+    // Every path a commit put the blob at, sorted.
+    pub paths: Vec<String>,
+    pub commit: Option<String>,
+    pub author: Option<String>,
+}
+
+// Every unmerged blob of at least `min_size` bytes on disk, largest first,
+// whatever the selected object types. Membership comes from the shared
+// walk; only the commits are looked up again, in one pass over all healthy
+// branches.
+pub fn find_large_objects<G: GitOps>(
+    git: &G,
+    repo_path: &Path,
+    analysis: &Analysis,
+    min_size: u64,
+) -> Result<Vec<LargeObject>> {
+    let mut blobs: Vec<(Oid, u64)> = analysis
+        .object_map
+        .iter()
+        .filter(|(_, info)| info.kind == ObjectKind::Blob && info.size >= min_size)
+        .map(|(oid, info)| (*oid, info.size))
+        .collect();
+    blobs.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    if blobs.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut tips: Vec<String> = analysis
+        .branches
+        .iter()
+        .zip(&analysis.statuses)
+        .filter(|(_, status)| **status == BranchStatus::Ok)
+        .map(|(branch, _)| branch.tip.clone())
+        .collect();
+    tips.sort_unstable();
+    tips.dedup();
+    let oids: Vec<Oid> = blobs.iter().map(|(oid, _)| *oid).collect();
+    let changes = git.find_blob_changes(repo_path, &tips, &analysis.bases, &oids)?;
+
+    let mut by_blob: FxHashMap<Oid, Vec<BlobChange>> = FxHashMap::default();
+    for change in changes {
+        by_blob.entry(change.blob).or_default().push(change);
+    }
+
+    Ok(blobs
+        .into_iter()
+        .map(|(oid, size)| {
+            let changes = by_blob.remove(&oid).unwrap_or_default();
+            // an ancestor always has the lower generation; dates only break
+            // ties between commits on unrelated lines
+            let first = changes.iter().min_by_key(|c| (c.generation, c.time, &c.commit));
+            let mut paths: Vec<String> = changes.iter().map(|c| c.path.clone()).collect();
+            paths.sort_unstable();
+            paths.dedup();
+            LargeObject {
+                blob: oid.to_string(),
+                size,
+                branches: analysis.branches_holding(&oid).to_vec(),
+                paths,
+                commit: first.map(|c| c.commit.clone()),
+                author: first.map(|c| c.author.clone()),
+            }
+        })
+        .collect())
+}
+
+// Walks all healthy tips not served from the cache at once. If that fails,
+// each tip is walked on its own to find the branches that cannot be read;
+// those are marked failed and the walk is retried without them.
+fn collect_objects<G: GitOps>(
+    git: &G,
+    repo_path: &Path,
+    branches: &[BranchRef],
+    statuses: &mut [BranchStatus],
+    cached: &[bool],
+    bases: &[String],
+    delta_info: bool,
+) -> Result<UnmergedObjects> {
+    let healthy_tips = |statuses: &[BranchStatus]| {
+        let mut tips: Vec<String> = branches
+            .iter()
+            .zip(statuses)
+            .zip(cached)
+            .filter(|((_, status), &cached)| **status == BranchStatus::Ok && !cached)
+            .map(|((b, _), _)| b.tip.clone())
+            .collect();
+        tips.sort();
+        tips.dedup();
+        tips
+    };
+
+    let tips = healthy_tips(statuses);
+    if tips.is_empty() {
+        return Ok(UnmergedObjects::default());
+    }
+
+    println!("Collecting unmerged objects reachable from {} branch tips...", tips.len());
+    let err = match git.get_unmerged_objects(repo_path, &tips, bases, delta_info) {
+        Ok(objects) => return Ok(objects),
+        Err(err) => err,
+    };
+
+    println!("Warning: walk failed ({:#}); checking branch tips one by one...", err);
+    let failures: FxHashMap<&String, String> = tips
+        .par_iter()
+        .filter_map(|tip| {
+            let result = git.get_unmerged_objects(repo_path, std::slice::from_ref(tip), bases, delta_info);
+            result.err().map(|err| (tip, format!("{:#}", err)))
+        })
+        .collect();
+
+    // nothing branch specific, e.g. an unreadable base
+    if failures.is_empty() {
+        return Err(err);
+    }
+
+    for (branch, status) in branches.iter().zip(statuses.iter_mut()) {
+        if let Some(reason) = failures.get(&branch.tip) {
+            println!("Warning: cannot scan {}: {}", branch.refname, reason);
+            *status = BranchStatus::Failed(reason.clone());
+        }
+    }
+
+    let tips = healthy_tips(statuses);
+    if tips.is_empty() {
+        return Ok(UnmergedObjects::default());
+    }
+    git.get_unmerged_objects(repo_path, &tips, bases, delta_info)
+}
+
+// Base tips are part of every cache key, so a base that does not resolve to a
+// commit means running without the cache, as does an unusable directory.
+fn open_cache<G: GitOps>(git: &G, repo_path: &Path, dir: &Path, bases: &[String]) -> Option<BranchCache> {
+    let mut base_tips = Vec::new();
+    for base in bases {
+        match git.resolve_commit(repo_path, base) {
+            Ok(Some(tip)) => base_tips.push(tip),
+            _ => {
+                println!("Warning: cannot resolve {}, not using the cache", base);
+                return None;
+            }
         }
     }
 
-    object_map
+    match BranchCache::open(dir, base_tips) {
+        Ok(cache) => Some(cache),
+        Err(err) => {
+            println!("Warning: not using the cache: {:#}", err);
+            None
+        }
+    }
+}
+
+// Merges the cached object lists of unchanged branches into the membership
+// map and returns which branches were served from the cache. Branches on the
+// same tip share one entry.
+fn load_cached(
+    cache: &BranchCache,
+    branches: &[BranchRef],
+    statuses: &[BranchStatus],
+    object_map: &mut FxHashMap<Oid, ObjectInfo>,
+    branch_sets: &mut BranchSets,
+    mut cached_deltas: Option<&mut CachedDeltas>,
+    mut labels: Option<&mut PathLabels>,
+) -> Vec<bool> {
+    let delta_info = cached_deltas.is_some();
+    let mut by_tip: FxHashMap<&str, Vec<u32>> = FxHashMap::default();
+    for (idx, (branch, status)) in branches.iter().zip(statuses).enumerate() {
+        if *status == BranchStatus::Ok {
+            by_tip.entry(branch.tip.as_str()).or_default().push(idx as u32);
+        }
+    }
+    let mut by_tip: Vec<(&str, Vec<u32>)> = by_tip.into_iter().collect();
+    by_tip.sort_unstable();
+
+    let mut cached = vec![false; branches.len()];
+    // a few entries at a time, so only a handful of lists are in memory
+    for chunk in by_tip.chunks(rayon::current_num_threads() * 4) {
+        let loaded: Vec<Option<UnmergedObjects>> =
+            chunk.par_iter().map(|(tip, _)| cache.load(tip, delta_info)).collect();
+
+        for ((_, members), objects) in chunk.iter().zip(loaded) {
+            let Some(objects) = objects else { continue };
+            for &idx in members {
+                cached[idx as usize] = true;
+            }
+            let set = branch_sets.intern(members.clone());
+            for (node, object) in objects.iter().enumerate() {
+                object_map
+                    .entry(object.oid)
+                    .and_modify(|info| info.branches = branch_sets.union(info.branches, set))
+                    .or_insert(ObjectInfo { kind: object.kind, size: object.size, branches: set });
+                if let Some(cached_deltas) = cached_deltas.as_deref_mut() {
+                    cached_deltas.insert(object.oid, (object.raw_size, objects.delta_base(node)));
+                }
+                if let Some(labels) = labels.as_deref_mut() {
+                    labels.record(object, objects.path(node));
+                }
+            }
+        }
+    }
+
+    cached
+}
+
+// Writes the object list of every branch walked this run. Objects are grouped
+// by branch set first, so a branch's list is just the groups whose set
+// contains it.
+fn store_scanned(
+    cache: &BranchCache,
+    branches: &[BranchRef],
+    scanned: &[bool],
+    objects: &UnmergedObjects,
+    object_map: &FxHashMap<Oid, ObjectInfo>,
+    branch_sets: &BranchSets,
+    delta_info: bool,
+) {
+    let mut by_set: FxHashMap<BranchSetId, Vec<u32>> = FxHashMap::default();
+    for (idx, object) in objects.iter().enumerate() {
+        if let Some(info) = object_map.get(&object.oid) {
+            by_set.entry(info.branches).or_default().push(idx as u32);
+        }
+    }
+
+    let mut sets_of: Vec<Vec<BranchSetId>> = vec![Vec::new(); branches.len()];
+    for &set in by_set.keys() {
+        for &member in branch_sets.get(set) {
+            if scanned[member as usize] {
+                sets_of[member as usize].push(set);
+            }
+        }
+    }
+
+    let mut tips: FxHashMap<&str, usize> = FxHashMap::default();
+    for (idx, branch) in branches.iter().enumerate() {
+        if scanned[idx] {
+            tips.entry(branch.tip.as_str()).or_insert(idx);
+        }
+    }
+    let tips: Vec<(&str, usize)> = tips.into_iter().collect();
+
+    let failed: Vec<_> = tips
+        .par_iter()
+        .filter_map(|&(tip, idx)| {
+            let listed = sets_of[idx].iter().flat_map(|set| &by_set[set]).map(|&i| i as usize);
+            cache.store(tip, delta_info, objects, listed).err()
+        })
+        .collect();
+    if let Some(err) = failed.first() {
+        println!("Warning: cannot write {} cache entries: {:#}", failed.len(), err);
+    }
+}
+
+// Propagates branch membership from the tips down the unmerged object graph
+// in topological order, so every object ends up with the set of branches that
+// reach it. Each object is visited once no matter how many branches share it.
+// Membership already in `object_map` (from the cache) is kept.
+fn attribute_objects(
+    branches: &[BranchRef],
+    objects: &UnmergedObjects,
+    object_map: &mut FxHashMap<Oid, ObjectInfo>,
+    branch_sets: &mut BranchSets,
+) {
+    let mut pending = vec![0u32; objects.len()];
+    for node in 0..objects.len() {
+        for &child in objects.children(node) {
+            pending[child as usize] += 1;
+        }
+    }
+
+    // a pass over the table instead of an index of every object
+    let mut branches_at: FxHashMap<Oid, Vec<u32>> = FxHashMap::default();
+    for (branch_idx, branch) in branches.iter().enumerate() {
+        if let Some(tip) = Oid::from_hex(&branch.tip) {
+            branches_at.entry(tip).or_default().push(branch_idx as u32);
+        }
+    }
+    let mut tip_branches: FxHashMap<u32, Vec<u32>> = FxHashMap::default();
+    for (node, object) in objects.iter().enumerate() {
+        if let Some(members) = branches_at.remove(&object.oid) {
+            tip_branches.insert(node as u32, members);
+        }
+    }
+
+    let mut sets: Vec<BranchSetId> = vec![0; objects.len()];
+    for (node, members) in tip_branches {
+        sets[node as usize] = branch_sets.intern(members);
+    }
+
+    let mut queue: Vec<u32> = (0..objects.len() as u32).filter(|&i| pending[i as usize] == 0).collect();
+
+    while let Some(node) = queue.pop() {
+        let node = node as usize;
+        let set = sets[node];
+
+        for &child in objects.children(node) {
+            let child = child as usize;
+            sets[child] = branch_sets.union(sets[child], set);
+            pending[child] -= 1;
+            if pending[child] == 0 {
+                queue.push(child as u32);
+            }
+        }
+
+        let object = &objects[node];
+        if set != 0 {
+            object_map
+                .entry(object.oid)
+                .and_modify(|info| info.branches = branch_sets.union(info.branches, set))
+                .or_insert(ObjectInfo { kind: object.kind, size: object.size, branches: set });
+        }
+    }
+}
+
+// (local, upstream) pairs of healthy branches whose upstream was scanned
+// too. Two local branches following the same upstream leave it to the first.
+fn upstream_groups(branches: &[BranchRef], statuses: &[BranchStatus]) -> Vec<(usize, usize)> {
+    let healthy: FxHashMap<&str, usize> = branches
+        .iter()
+        .enumerate()
+        .filter(|&(i, _)| statuses[i] == BranchStatus::Ok)
+        .map(|(i, b)| (b.refname.as_str(), i))
+        .collect();
+
+    let mut claimed = FxHashSet::default();
+    let mut groups = Vec::new();
+    for (local, branch) in branches.iter().enumerate() {
+        if statuses[local] != BranchStatus::Ok || RefKind::of(&branch.refname) != RefKind::Local {
+            continue;
+        }
+        let Some(&upstream) = branch.upstream.as_deref().and_then(|u| healthy.get(u)) else { continue };
+        if claimed.insert(upstream) {
+            groups.push((local, upstream));
+        }
+    }
+    groups
+}
+
+// Unmerged commits only the local branch reaches and only its upstream
+// reaches, per group. Commits both sides lack because a base holds them do
+// not count, unlike in `git status`.
+fn divergence(
+    groups: &[(usize, usize)],
+    branch_count: usize,
+    object_map: &FxHashMap<Oid, ObjectInfo>,
+    branch_sets: &BranchSets,
+) -> Vec<(usize, usize)> {
+    if groups.is_empty() {
+        return Vec::new();
+    }
+    let mut commits: FxHashMap<BranchSetId, usize> = FxHashMap::default();
+    for info in object_map.values().filter(|info| info.kind == ObjectKind::Commit) {
+        *commits.entry(info.branches).or_default() += 1;
+    }
+
+    // group and side of each grouped branch, plus the other side
+    let mut roles: Vec<Option<(usize, bool, u32)>> = vec![None; branch_count];
+    for (g, &(local, upstream)) in groups.iter().enumerate() {
+        roles[local] = Some((g, true, upstream as u32));
+        roles[upstream] = Some((g, false, local as u32));
+    }
+
+    let mut divergence = vec![(0, 0); groups.len()];
+    for (&set, &count) in &commits {
+        let members = branch_sets.get(set);
+        for &member in members {
+            let Some((g, is_local, other)) = roles[member as usize] else { continue };
+            if members.binary_search(&other).is_err() {
+                if is_local {
+                    divergence[g].0 += count;
+                } else {
+                    divergence[g].1 += count;
+                }
+            }
+        }
+    }
+    divergence
+}
+
+// Replaces every grouped upstream by its local branch in the objects' branch
+// sets, so what only the pair reaches counts as unique to the local entry.
+fn merge_upstreams(
+    groups: &[(usize, usize)],
+    branch_count: usize,
+    object_map: &mut FxHashMap<Oid, ObjectInfo>,
+    branch_sets: &mut BranchSets,
+) {
+    let mut target: Vec<u32> = (0..branch_count as u32).collect();
+    for &(local, upstream) in groups {
+        target[upstream] = local as u32;
+    }
+
+    let mut merged = BranchSets::new();
+    let mut remap: FxHashMap<BranchSetId, BranchSetId> = FxHashMap::default();
+    for info in object_map.values_mut() {
+        info.branches = *remap.entry(info.branches).or_insert_with(|| {
+            merged.intern(branch_sets.get(info.branches).iter().map(|&m| target[m as usize]).collect())
+        });
+    }
+    *branch_sets = merged;
+}
+
+fn group_deltas(
+    objects: &UnmergedObjects,
+    mut cached_deltas: CachedDeltas,
+    object_map: &FxHashMap<Oid, ObjectInfo>,
+) -> DeltaGroups {
+    for object in objects.iter() {
+        cached_deltas.remove(&object.oid);
+    }
+    let walked = objects.iter().enumerate().map(|(node, o)| (o.oid, o.raw_size, objects.delta_base(node)));
+    let from_cache = cached_deltas.into_iter().map(|(oid, (raw_size, base))| (oid, raw_size, base));
+
+    let mut delta_groups = DeltaGroups::default();
+    for (oid, raw_size, delta_base) in walked.chain(from_cache) {
+        let Some(info) = object_map.get(&oid) else { continue };
+        // a base outside the unmerged objects is reachable from the
+        // default branch, so no branch pack would carry it
+        let base_set = match delta_base {
+            Some(base) => object_map.get(&base).map_or(0, |base| base.branches),
+            None => info.branches,
+        };
+        let group = delta_groups.entry((info.kind, info.branches, base_set)).or_default();
+        group.disk += info.size;
+        group.raw += raw_size;
+        group.count += 1;
+    }
+    delta_groups
+}
+
+fn delta_stats(
+    branch_count: usize,
+    delta_groups: &DeltaGroups,
+    branch_sets: &BranchSets,
+    object_types: &[ObjectKind],
+) -> Vec<DeltaStats> {
+    let mut stats = vec![DeltaStats::default(); branch_count];
+    for (&(kind, set, base_set), group) in delta_groups {
+        if !object_types.contains(&kind) {
+            continue;
+        }
+        let base_members = branch_sets.get(base_set);
+        for &branch_idx in branch_sets.get(set) {
+            let entry = &mut stats[branch_idx as usize];
+            entry.raw_size += group.raw;
+            if base_members.binary_search(&branch_idx).is_ok() {
+                entry.standalone_size += group.disk;
+            } else {
+                entry.standalone_size += group.raw;
+                entry.external_delta_count += group.count;
+            }
+        }
+    }
+    stats
+}
+
+// Sum per interned set first; each set's totals are then credited to its
+// members once, instead of once per object.
+fn sum_per_set(object_map: &FxHashMap<Oid, ObjectInfo>, branch_sets: &BranchSets) -> Vec<KindBreakdown> {
+    let mut per_set: Vec<KindBreakdown> = vec![KindBreakdown::default(); branch_sets.len()];
+    for info in object_map.values() {
+        let entry = per_set[info.branches as usize].get_mut(info.kind);
+        entry.size += info.size;
+        entry.count += 1;
+    }
+    per_set
+}
+
+// Per branch and across all branches. Like `sum_per_set`, blobs are summed
+// per (set, label) first and each sum is credited to the set's members.
+fn path_breakdowns(
+    branch_count: usize,
+    labels: &PathLabels,
+    object_map: &FxHashMap<Oid, ObjectInfo>,
+    branch_sets: &BranchSets,
+) -> (Vec<PathBreakdown>, PathBreakdown) {
+    type Totals = FxHashMap<u32, KindStats>;
+    fn add(stats: &mut KindStats, size: u64, count: usize) {
+        stats.size += size;
+        stats.count += count;
+    }
+
+    // [directories, extensions]
+    let mut per_set: [FxHashMap<(BranchSetId, u32), KindStats>; 2] = Default::default();
+    let mut rollup: [Totals; 2] = Default::default();
+    for (oid, &(directory, extension)) in &labels.blobs {
+        let Some(info) = object_map.get(oid) else { continue };
+        for (i, label) in [directory, extension].into_iter().enumerate() {
+            add(per_set[i].entry((info.branches, label)).or_default(), info.size, 1);
+            add(rollup[i].entry(label).or_default(), info.size, 1);
+        }
+    }
+
+    let mut per_branch: Vec<[Totals; 2]> = vec![Default::default(); branch_count];
+    for (i, sums) in per_set.iter().enumerate() {
+        for (&(set, label), stats) in sums {
+            for &member in branch_sets.get(set) {
+                add(per_branch[member as usize][i].entry(label).or_default(), stats.size, stats.count);
+            }
+        }
+    }
+
+    let sorted = |totals: &Totals| {
+        let mut sorted: Vec<(String, KindStats)> =
+            totals.iter().map(|(&label, &stats)| (labels.names[label as usize].clone(), stats)).collect();
+        sorted.sort_by(|a, b| b.1.size.cmp(&a.1.size).then_with(|| a.0.cmp(&b.0)));
+        sorted
+    };
+    let breakdown = |[directories, extensions]: &[Totals; 2]| PathBreakdown {
+        directories: sorted(directories),
+        extensions: sorted(extensions),
+    };
+    (per_branch.iter().map(breakdown).collect(), breakdown(&rollup))
+}
+
+// Reads every unmerged blob small enough to be a pointer and sums up what
+// the pointers refer to, per branch and across branches. A failure here is
+// only a warning: the git object sizes stand on their own.
+fn lfs_stats<G: GitOps>(
+    git: &G,
+    repo_path: &Path,
+    branch_count: usize,
+    object_map: &FxHashMap<Oid, ObjectInfo>,
+    branch_sets: &BranchSets,
+) -> Option<(Vec<LfsStats>, LfsStats)> {
+    // Only a cheap prefilter: `size` is the on-disk size, compressed or a
+    // delta, so larger blobs can slip through. `get_small_blobs` checks the
+    // real size and keeps only blobs under it. A pointer is a few hundred bytes,
+    // which zlib never inflates past the limit, so none is filtered out here.
+    let mut candidates: Vec<Oid> = object_map
+        .iter()
+        .filter(|(_, info)| info.kind == ObjectKind::Blob && info.size < lfs::POINTER_MAX_SIZE)
+        .map(|(&oid, _)| oid)
+        .collect();
+    candidates.sort_unstable();
+    println!("Checking {} small blobs for LFS pointers...", candidates.len());
+
+    let read = git.git_dir(repo_path).and_then(|git_dir| {
+        let blobs = git.get_small_blobs(repo_path, &candidates, lfs::POINTER_MAX_SIZE)?;
+        Ok((LfsStore::open(&git_dir), blobs))
+    });
+    let (store, blobs) = match read {
+        Ok(read) => read,
+        Err(err) => {
+            println!("Warning: cannot look for LFS pointers: {:#}", err);
+            return None;
+        }
+    };
+
+    let mut per_set: FxHashMap<BranchSetId, LfsStats> = FxHashMap::default();
+    let mut total = LfsStats::default();
+    for (oid, content) in blobs {
+        let Some(pointer) = lfs::parse_pointer(&content) else { continue };
+        let local_size = store.local_size(&pointer);
+        let stats = LfsStats {
+            size: pointer.size,
+            local_size: local_size.unwrap_or(0),
+            count: 1,
+            missing_count: usize::from(local_size.is_none()),
+        };
+        per_set.entry(object_map[&oid].branches).or_default().add(&stats);
+        total.add(&stats);
+    }
+
+    let mut per_branch = vec![LfsStats::default(); branch_count];
+    for (set, stats) in &per_set {
+        for &member in branch_sets.get(*set) {
+            per_branch[member as usize].add(stats);
+        }
+    }
+    Some((per_branch, total))
 }
 
 fn calculate_weights(
-    branch_names: &[String],
-    object_map: &FxHashMap<String, ObjectInfo>,
+    branches: &[BranchRef],
+    per_set: &[KindBreakdown],
+    branch_sets: &BranchSets,
+    object_types: &[ObjectKind],
+    delta: Option<&[DeltaStats]>,
+    paths: Option<&[PathBreakdown]>,
+    lfs: Option<&[LfsStats]>,
 ) -> Vec<BranchWeight> {
-    let branch_count = branch_names.len();
+    let branch_count = branches.len();
     let mut branch_stats: Vec<(u64, u64, usize, usize)> = vec![(0, 0, 0, 0); branch_count];
+    let mut branch_kinds: Vec<KindBreakdown> = vec![KindBreakdown::default(); branch_count];
+    let mut attributed: Vec<u64> = vec![0; branch_count];
 
-    for info in object_map.values() {
-        let is_shared = info.branches.len() > 1;
+    for (set_id, breakdown) in per_set.iter().enumerate() {
+        let members = branch_sets.get(set_id as BranchSetId);
+        let is_shared = members.len() > 1;
 
-        for &branch_idx in &info.branches {
-            let entry = &mut branch_stats[branch_idx as usize];
-            if is_shared {
-                entry.1 += info.size;
-                entry.3 += 1;
-            } else {
-                entry.0 += info.size;
-                entry.2 += 1;
+        for kind in ObjectKind::ALL {
+            let stats = *breakdown.get(kind);
+            if stats.count == 0 {
+                continue;
+            }
+            let selected = object_types.contains(&kind);
+
+            // The remainder of the split goes to the first members so the
+            // shares add up to the set's size exactly.
+            let share = stats.size / members.len() as u64;
+            let remainder = (stats.size % members.len() as u64) as usize;
+
+            for (pos, &branch_idx) in members.iter().enumerate() {
+                let totals = branch_kinds[branch_idx as usize].get_mut(kind);
+                totals.size += stats.size;
+                totals.count += stats.count;
+
+                if !selected {
+                    continue;
+                }
+                attributed[branch_idx as usize] += share + u64::from(pos < remainder);
+
+                let entry = &mut branch_stats[branch_idx as usize];
+                if is_shared {
+                    entry.1 += stats.size;
+                    entry.3 += stats.count;
+                } else {
+                    entry.0 += stats.size;
+                    entry.2 += stats.count;
+                }
             }
         }
     }
@@ -168,18 +1838,27 @@ fn calculate_weights(
         .filter(|(_, (u, s, _, _))| *u > 0 || *s > 0)
         .map(|(i, (unique_size, shared_size, unique_count, shared_count))| {
             BranchWeight {
-                branch: branch_names[i].clone(),
+                branch: branches[i].name.clone(),
+                refname: branches[i].refname.clone(),
+                tip: branches[i].tip.clone(),
                 unique_size,
                 shared_size,
                 total_size: unique_size + shared_size,
                 object_count: unique_count + shared_count,
                 unique_count,
                 shared_count,
+                attributed_size: attributed[i],
+                by_kind: branch_kinds[i],
+                delta: delta.map(|stats| stats[i]),
+                paths: paths.map(|paths| paths[i].clone()),
+                lfs: lfs.map(|stats| stats[i]),
+                last_commit: branches[i].last_commit.clone(),
+                upstream: None,
             }
         })
         .collect();
 
-    results.sort_by(|a, b| b.total_size.cmp(&a.total_size));
+    results.sort_by_key(|b| std::cmp::Reverse(b.total_size));
 
     results
 }
@@ -187,28 +1866,154 @@ fn calculate_weights(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::git::{BrokenRef, LinkedObject};
     use std::collections::HashMap;
 
+    fn fake_oid(name: &str) -> Oid {
+        let mut bytes = [0u8; 20];
+        for (i, b) in name.bytes().take(20).enumerate() {
+            bytes[i] = b;
+        }
+        Oid::Sha1(bytes)
+    }
+
+    fn lfs_oid(name: &str) -> String {
+        format!("{:0>64}", name.bytes().map(|b| format!("{:02x}", b)).collect::<String>())
+    }
+
+    // Each mock branch was last committed 30 days before the one listed
+    // ahead of it, the first one at MOCK_NOW.
+    const MOCK_NOW: i64 = 1_700_000_000;
+
     struct MockGit {
         branches: Vec<(String, String)>,
         blobs: HashMap<String, HashMap<String, u64>>,
+        parents: HashMap<String, Vec<String>>,
     }
 
     impl GitOps for MockGit {
-        fn get_branches(&self, _repo: &Path) -> Result<Vec<(String, String)>> {
-            Ok(self.branches.clone())
+        fn list_refs(&self, repo: &Path) -> Result<Vec<(String, String)>> {
+            let (branches, _) = self.get_branches(repo, &[])?;
+            Ok(branches.into_iter().map(|b| (b.refname, b.tip)).collect())
+        }
+
+        fn get_branches(&self, _repo: &Path, _bases: &[String]) -> Result<(Vec<BranchRef>, Vec<BrokenRef>)> {
+            let branches = self
+                .branches
+                .iter()
+                .enumerate()
+                .map(|(i, (name, tip))| {
+                    let last_commit = CommitInfo {
+                        date: MOCK_NOW - 30 * 86400 * i as i64,
+                        author_name: "Dev".to_string(),
+                        author_email: "dev@example.com".to_string(),
+                        subject: name.clone(),
+                    };
+                    // `origin/x` is a remote-tracking branch and the upstream of `x`
+                    let refname = match name.strip_prefix("origin/") {
+                        Some(_) => format!("refs/remotes/{}", name),
+                        None => format!("refs/heads/{}", name),
+                    };
+                    let remote = format!("origin/{}", name);
+                    let upstream = self
+                        .branches
+                        .iter()
+                        .any(|(other, _)| *other == remote)
+                        .then(|| format!("refs/remotes/{}", remote));
+                    // tips named `undated*` have no readable date
+                    let last_commit = Some(last_commit).filter(|_| !tip.starts_with("undated"));
+                    BranchRef {
+                        last_commit,
+                        upstream,
+                        ..BranchRef::new(refname, fake_oid(tip).to_string())
+                    }
+                })
+                .collect();
+            Ok((branches, Vec::new()))
         }
 
-        fn get_unmerged_blobs(&self, _repo: &Path, branch: &str, _exclude: &str) -> Result<HashMap<String, u64>> {
-            Ok(self.blobs.get(branch).cloned().unwrap_or_default())
+        fn get_unmerged_objects(
+            &self,
+            _repo: &Path,
+            _tips: &[String],
+            _exclude: &[String],
+            _delta_info: bool,
+        ) -> Result<UnmergedObjects> {
+            let mut objects = Vec::new();
+            let mut seen = FxHashSet::default();
+
+            for (commit, blobs) in &self.blobs {
+                let mut children: Vec<Oid> = blobs.keys().map(|b| fake_oid(b)).collect();
+                children.extend(self.parents.get(commit).into_iter().flatten().map(|p| fake_oid(p)));
+                objects.push(LinkedObject { oid: fake_oid(commit), kind: ObjectKind::Commit, size: 200, raw_size: 200, delta_base: None, children, path: None });
+
+                for (name, &size) in blobs {
+                    if seen.insert(name.clone()) {
+                        objects.push(LinkedObject {
+                            oid: fake_oid(name),
+                            kind: ObjectKind::Blob,
+                            size,
+                            raw_size: size,
+                            delta_base: None,
+                            children: Vec::new(),
+                            path: Some(format!("files/{}", name)),
+                        });
+                    }
+                }
+            }
+
+            Ok(UnmergedObjects::link(objects))
         }
 
-        fn get_unmerged_commits(&self, _repo: &Path, _branch: &str, _exclude: &str) -> Result<Vec<crate::git::CommitBlobs>> {
+        fn get_unmerged_commits(&self, _repo: &Path, _branch: &str, _exclude: &[String]) -> Result<Vec<crate::git::CommitBlobs>> {
             Ok(Vec::new())
         }
 
-        fn detect_default_branch(&self, _repo: &Path) -> Result<String> {
-            Ok("refs/heads/master".to_string())
+        // Blobs named `lfs_*` are pointers to content ten times their size.
+        fn get_small_blobs(&self, _repo: &Path, oids: &[Oid], _max_size: u64) -> Result<Vec<(Oid, Vec<u8>)>> {
+            let mut blobs = Vec::new();
+            for (name, &size) in self.blobs.values().flatten() {
+                if name.starts_with("lfs_") && oids.contains(&fake_oid(name)) && !blobs.iter().any(|(oid, _)| *oid == fake_oid(name)) {
+                    let pointer = format!("version https://git-lfs.github.com/spec/v1\noid sha256:{}\nsize {}\n", lfs_oid(name), size * 10);
+                    blobs.push((fake_oid(name), pointer.into_bytes()));
+                }
+            }
+            Ok(blobs)
+        }
+
+        // Every commit adds its blobs under a directory named after it; commits
+        // are timestamped in name order.
+        fn find_blob_changes(&self, _repo: &Path, _tips: &[String], _exclude: &[String], blobs: &[Oid]) -> Result<Vec<BlobChange>> {
+            // 1 plus the generation of the deepest parent, counting parents
+            // that add no blobs, so generations can skip numbers
+            fn generation(mock: &MockGit, commit: &str) -> u32 {
+                let parents = mock.parents.get(commit).into_iter().flatten();
+                parents.map(|p| generation(mock, p)).max().unwrap_or(0) + 1
+            }
+            let mut commits: Vec<&String> = self.blobs.keys().collect();
+            commits.sort();
+            let mut changes = Vec::new();
+            for (time, commit) in commits.into_iter().enumerate() {
+                for name in self.blobs[commit].keys().filter(|name| blobs.contains(&fake_oid(name))) {
+                    changes.push(BlobChange {
+                        blob: fake_oid(name),
+                        path: format!("{}/{}", commit, name),
+                        commit: fake_oid(commit).to_string(),
+                        author: format!("Dev <{}@example.com>", commit),
+                        time: time as i64,
+                        generation: generation(self, commit),
+                    });
+                }
+            }
+            Ok(changes)
+        }
+
+        fn resolve_commit(&self, _repo: &Path, rev: &str) -> Result<Option<String>> {
+            Ok((rev == "refs/heads/master").then(|| fake_oid("master").to_string()))
+        }
+
+        fn git_dir(&self, repo: &Path) -> Result<PathBuf> {
+            Ok(repo.join(".git"))
         }
     }
 
@@ -217,9 +2022,10 @@ mod tests {
         let mock = MockGit {
             branches: vec![],
             blobs: HashMap::new(),
+            parents: HashMap::new(),
         };
 
-        let result = analyze_branches(&mock, Path::new("/fake"), "refs/heads/master").unwrap();
+        let result = analyze_branches(&mock, Path::new("/fake"), &AnalysisOptions::new()).unwrap().weights;
         assert!(result.is_empty());
     }
 
@@ -234,9 +2040,10 @@ mod tests {
         let mock = MockGit {
             branches: vec![("feature/test".to_string(), "abc123".to_string())],
             blobs,
+            parents: HashMap::new(),
         };
 
-        let result = analyze_branches(&mock, Path::new("/fake"), "refs/heads/master").unwrap();
+        let result = analyze_branches(&mock, Path::new("/fake"), &AnalysisOptions::new()).unwrap().weights;
 
         assert_eq!(result.len(), 1);
         assert_eq!(result[0].branch, "feature/test");
@@ -265,9 +2072,10 @@ mod tests {
                 ("feature/b".to_string(), "branch2".to_string()),
             ],
             blobs,
+            parents: HashMap::new(),
         };
 
-        let result = analyze_branches(&mock, Path::new("/fake"), "refs/heads/master").unwrap();
+        let result = analyze_branches(&mock, Path::new("/fake"), &AnalysisOptions::new()).unwrap().weights;
 
         assert_eq!(result.len(), 2);
 
@@ -279,6 +2087,9 @@ mod tests {
 
         assert_eq!(branch_b.unique_size, 700);
         assert_eq!(branch_b.shared_size, 1000);
+
+        assert_eq!(branch_a.attributed_size, 1000);
+        assert_eq!(branch_b.attributed_size, 1200);
     }
 
     #[test]
@@ -295,9 +2106,10 @@ mod tests {
                 ("medium-branch".to_string(), "medium".to_string()),
             ],
             blobs,
+            parents: HashMap::new(),
         };
 
-        let result = analyze_branches(&mock, Path::new("/fake"), "refs/heads/master").unwrap();
+        let result = analyze_branches(&mock, Path::new("/fake"), &AnalysisOptions::new()).unwrap().weights;
 
         assert_eq!(result[0].branch, "large-branch");
         assert_eq!(result[1].branch, "medium-branch");
@@ -316,11 +2128,745 @@ mod tests {
                 ("empty-branch".to_string(), "empty".to_string()),
             ],
             blobs,
+            parents: HashMap::new(),
         };
 
-        let result = analyze_branches(&mock, Path::new("/fake"), "refs/heads/master").unwrap();
+        let result = analyze_branches(&mock, Path::new("/fake"), &AnalysisOptions::new()).unwrap().weights;
 
         assert_eq!(result.len(), 1);
         assert_eq!(result[0].branch, "with-objects");
     }
+
+    #[test]
+    fn test_ancestor_branch_objects_are_shared() {
+        let mut blobs = HashMap::new();
+        blobs.insert("old_tip".to_string(), HashMap::from([("base_obj".to_string(), 400u64)]));
+        blobs.insert("new_tip".to_string(), HashMap::from([("new_obj".to_string(), 600u64)]));
+
+        let mock = MockGit {
+            branches: vec![
+                ("feature/old".to_string(), "old_tip".to_string()),
+                ("feature/new".to_string(), "new_tip".to_string()),
+            ],
+            blobs,
+            parents: HashMap::from([("new_tip".to_string(), vec!["old_tip".to_string()])]),
+        };
+
+        let result = analyze_branches(&mock, Path::new("/fake"), &AnalysisOptions::new()).unwrap().weights;
+
+        let old = result.iter().find(|b| b.branch == "feature/old").unwrap();
+        let new = result.iter().find(|b| b.branch == "feature/new").unwrap();
+
+        assert_eq!(old.unique_size, 0);
+        assert_eq!(old.shared_size, 400);
+        assert_eq!(new.unique_size, 600);
+        assert_eq!(new.shared_size, 400);
+        assert_eq!(new.total_size, 1000);
+    }
+
+    #[test]
+    fn test_branch_sets_interned_and_unioned() {
+        let mut sets = BranchSets::new();
+        let a = sets.intern(vec![2, 0]);
+        let b = sets.intern(vec![0, 2, 2]);
+        assert_eq!(a, b);
+        assert_eq!(sets.get(a), &[0, 2]);
+
+        let c = sets.intern(vec![1]);
+        let ac = sets.union(a, c);
+        assert_eq!(sets.get(ac), &[0, 1, 2]);
+        assert_eq!(sets.union(c, a), ac);
+        assert_eq!(sets.union(ac, 0), ac);
+        assert_eq!(sets.len(), 4);
+    }
+
+    #[test]
+    fn test_object_types_select_what_counts() {
+        let mut blobs = HashMap::new();
+        blobs.insert("tip".to_string(), HashMap::from([("o1".to_string(), 1000u64)]));
+
+        let mock = MockGit {
+            branches: vec![("feature/x".to_string(), "tip".to_string())],
+            blobs,
+            parents: HashMap::new(),
+        };
+
+        let blobs_only = analyze_branches(&mock, Path::new("/fake"), &AnalysisOptions::new()).unwrap().weights;
+        assert_eq!(blobs_only[0].total_size, 1000);
+        assert_eq!(blobs_only[0].object_count, 1);
+        assert_eq!(blobs_only[0].by_kind.commits, KindStats { size: 200, count: 1 });
+        assert_eq!(blobs_only[0].by_kind.blobs, KindStats { size: 1000, count: 1 });
+
+        let everything = analyze_branches(&mock, Path::new("/fake"), &AnalysisOptions::new().object_types(ObjectKind::ALL)).unwrap().weights;
+        assert_eq!(everything[0].total_size, 1200);
+        assert_eq!(everything[0].unique_count, 2);
+        assert_eq!(everything[0].by_kind, blobs_only[0].by_kind);
+    }
+
+    #[test]
+    fn test_reclaimable_if_deleted_counts_objects_shared_within_set() {
+        let mut blobs = HashMap::new();
+        blobs.insert("a".to_string(), HashMap::from([
+            ("ab".to_string(), 1000u64),
+            ("abc".to_string(), 50u64),
+            ("only_a".to_string(), 200u64),
+        ]));
+        blobs.insert("b".to_string(), HashMap::from([
+            ("ab".to_string(), 1000u64),
+            ("abc".to_string(), 50u64),
+            ("only_b".to_string(), 300u64),
+        ]));
+        blobs.insert("c".to_string(), HashMap::from([("abc".to_string(), 50u64)]));
+
+        let mock = MockGit {
+            branches: vec![
+                ("feature/a".to_string(), "a".to_string()),
+                ("feature/b".to_string(), "b".to_string()),
+                ("feature/c".to_string(), "c".to_string()),
+            ],
+            blobs,
+            parents: HashMap::new(),
+        };
+
+        let analysis = analyze_branches(&mock, Path::new("/fake"), &AnalysisOptions::new()).unwrap();
+        let a = analysis.branch_index("feature/a").unwrap();
+        let b = analysis.branch_index("feature/b").unwrap();
+        let c = analysis.branch_index("feature/c").unwrap();
+
+        assert_eq!(analysis.reclaimable_if_deleted(&[a]).size, 200);
+
+        let ab = analysis.reclaimable_if_deleted(&[a, b]);
+        assert_eq!(ab.size, 1500);
+        assert_eq!(ab.count, 3);
+        assert_eq!(ab.marginal, vec![(b, 1300), (a, 1200)]);
+
+        assert_eq!(analysis.reclaimable_if_deleted(&[a, b, c]).size, 1550);
+        assert_eq!(analysis.reclaimable_if_deleted(&[c]).size, 0);
+    }
+
+    fn planner_fixture() -> Analysis {
+        let mut blobs = HashMap::new();
+        blobs.insert("a".to_string(), HashMap::from([
+            ("ab".to_string(), 5000u64),
+            ("only_a".to_string(), 100u64),
+        ]));
+        blobs.insert("b".to_string(), HashMap::from([
+            ("ab".to_string(), 5000u64),
+            ("only_b".to_string(), 300u64),
+        ]));
+        blobs.insert("c".to_string(), HashMap::from([("only_c".to_string(), 2000u64)]));
+
+        let mock = MockGit {
+            branches: vec![
+                ("feature/a".to_string(), "a".to_string()),
+                ("feature/b".to_string(), "b".to_string()),
+                ("feature/c".to_string(), "c".to_string()),
+            ],
+            blobs,
+            parents: HashMap::new(),
+        };
+
+        analyze_branches(&mock, Path::new("/fake"), &AnalysisOptions::new()).unwrap()
+    }
+
+    #[test]
+    fn test_plan_cleanup_top_k_accounts_for_shared_objects() {
+        let analysis = planner_fixture();
+        let steps = analysis.plan_cleanup(PlanGoal::TopK(3));
+        let names: Vec<&str> = steps.iter().map(|s| analysis.branches[s.branch as usize].name.as_str()).collect();
+
+        assert_eq!(names, vec!["feature/c", "feature/b", "feature/a"]);
+        assert_eq!(steps.iter().map(|s| s.reclaimed).collect::<Vec<_>>(), vec![2000, 300, 5100]);
+        assert_eq!(steps.last().unwrap().cumulative, 7400);
+    }
+
+    #[test]
+    fn test_plan_cleanup_stops_at_target() {
+        let analysis = planner_fixture();
+
+        let steps = analysis.plan_cleanup(PlanGoal::TargetBytes(2000));
+        assert_eq!(steps.len(), 1);
+        assert_eq!(steps[0].cumulative, 2000);
+
+        let steps = analysis.plan_cleanup(PlanGoal::TargetBytes(3000));
+        assert_eq!(steps.len(), 3);
+        assert_eq!(steps[2].cumulative, 7400);
+
+        let steps = analysis.plan_cleanup(PlanGoal::TargetBytes(u64::MAX));
+        assert_eq!(steps.len(), 3);
+    }
+
+    #[test]
+    fn test_attributed_sizes_sum_to_footprint() {
+        let mut blobs = HashMap::new();
+        for tip in ["a", "b", "c"] {
+            blobs.insert(tip.to_string(), HashMap::from([
+                ("everywhere".to_string(), 1001u64),
+                (format!("own_{}", tip), 10u64),
+            ]));
+        }
+
+        let mock = MockGit {
+            branches: vec![
+                ("feature/a".to_string(), "a".to_string()),
+                ("feature/b".to_string(), "b".to_string()),
+                ("feature/c".to_string(), "c".to_string()),
+            ],
+            blobs,
+            parents: HashMap::new(),
+        };
+
+        let result = analyze_branches(&mock, Path::new("/fake"), &AnalysisOptions::new()).unwrap().weights;
+
+        let attributed: u64 = result.iter().map(|b| b.attributed_size).sum();
+        assert_eq!(attributed, 1001 + 30);
+
+        let mut shares: Vec<u64> = result.iter().map(|b| b.attributed_size).collect();
+        shares.sort();
+        assert_eq!(shares, vec![343, 344, 344]);
+    }
+
+    #[test]
+    fn test_delta_stats_charge_external_bases_at_raw_size() {
+        let object = |name: &str, size: u64, raw_size: u64, base: Option<&str>, children: &[&str]| LinkedObject {
+            oid: fake_oid(name),
+            kind: ObjectKind::Blob,
+            size,
+            raw_size,
+            delta_base: base.map(fake_oid),
+            children: children.iter().map(|c| fake_oid(c)).collect(),
+            path: None,
+        };
+        // "a" holds v1 and a delta against it, "b" only the delta; "merged"
+        // is a base on the default branch.
+        let objects = UnmergedObjects::link(vec![
+            object("tip_a", 0, 0, None, &["v1", "v2"]),
+            object("tip_b", 0, 0, None, &["v2", "v3"]),
+            object("v1", 5000, 20000, None, &[]),
+            object("v2", 100, 20100, Some("v1"), &[]),
+            object("v3", 50, 30000, Some("merged"), &[]),
+        ]);
+        let branches = vec![
+            BranchRef::new("refs/heads/feature/a".to_string(), fake_oid("tip_a").to_string()),
+            BranchRef::new("refs/heads/feature/b".to_string(), fake_oid("tip_b").to_string()),
+        ];
+
+        let mut object_map = FxHashMap::default();
+        let mut branch_sets = BranchSets::new();
+        attribute_objects(&branches, &objects, &mut object_map, &mut branch_sets);
+        let groups = group_deltas(&objects, CachedDeltas::default(), &object_map);
+        let stats = delta_stats(2, &groups, &branch_sets, &[ObjectKind::Blob]);
+
+        assert_eq!(stats[0], DeltaStats { raw_size: 40100, standalone_size: 5100, external_delta_count: 0 });
+        assert_eq!(stats[1], DeltaStats { raw_size: 50100, standalone_size: 50100, external_delta_count: 2 });
+    }
+
+    #[test]
+    fn test_cached_branches_match_a_fresh_scan() {
+        let mut blobs = HashMap::new();
+        blobs.insert("a".to_string(), HashMap::from([("shared".to_string(), 500u64), ("only_a".to_string(), 100u64)]));
+        blobs.insert("b".to_string(), HashMap::from([("shared".to_string(), 500u64), ("only_b".to_string(), 300u64)]));
+        let mut mock = MockGit {
+            branches: vec![
+                ("feature/a".to_string(), "a".to_string()),
+                ("feature/b".to_string(), "b".to_string()),
+            ],
+            blobs,
+            parents: HashMap::new(),
+        };
+
+        let dir = std::env::temp_dir().join(format!("branch-weight-objects-cache-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let options = AnalysisOptions::new().delta_info(true).cache_dir(&dir);
+
+        let first = analyze_branches(&mock, Path::new("/fake"), &options).unwrap();
+        assert_eq!(first.cache, Some(CacheStats { hits: 0, misses: 2 }));
+        let second = analyze_branches(&mock, Path::new("/fake"), &options).unwrap();
+        assert_eq!(second.cache, Some(CacheStats { hits: 2, misses: 0 }));
+        assert_eq!(second.weights, first.weights);
+
+        mock.blobs.insert("c".to_string(), HashMap::from([("shared".to_string(), 500u64)]));
+        mock.branches.push(("feature/c".to_string(), "c".to_string()));
+        let third = analyze_branches(&mock, Path::new("/fake"), &options).unwrap();
+        assert_eq!(third.cache, Some(CacheStats { hits: 2, misses: 1 }));
+        let fresh = analyze_branches(&mock, Path::new("/fake"), &AnalysisOptions::new().delta_info(true)).unwrap();
+        assert_eq!(fresh.cache, None);
+        assert_eq!(third.weights, fresh.weights);
+
+        // a filtered run keeps the entries of the branches it left out
+        let filtered = analyze_branches(&mock, Path::new("/fake"), &options.clone().include("feature/a")).unwrap();
+        assert_eq!(filtered.cache, Some(CacheStats { hits: 1, misses: 0 }));
+        let fourth = analyze_branches(&mock, Path::new("/fake"), &options).unwrap();
+        assert_eq!(fourth.cache, Some(CacheStats { hits: 3, misses: 0 }));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_commits_credit_first_introduction_and_flag_readds() {
+        let object = |name: &str, kind: ObjectKind, size: u64, children: &[&str]| LinkedObject {
+            oid: fake_oid(name),
+            kind,
+            size,
+            raw_size: size,
+            delta_base: None,
+            children: children.iter().map(|c| fake_oid(c)).collect(),
+            path: None,
+        };
+        // c1 adds x and y, c2 changes x to x2, c3 reverts that and restores
+        // `base`, a blob from the default branch
+        let objects = UnmergedObjects::link(vec![
+            object("c3", ObjectKind::Commit, 200, &["t3", "c2"]),
+            object("c2", ObjectKind::Commit, 200, &["t2", "c1", "merged"]),
+            object("c1", ObjectKind::Commit, 200, &["t1", "merged"]),
+            object("t1", ObjectKind::Tree, 50, &["x", "y"]),
+            object("t2", ObjectKind::Tree, 50, &["x2", "y"]),
+            object("t3", ObjectKind::Tree, 60, &["x", "y", "base"]),
+            object("x", ObjectKind::Blob, 1000, &[]),
+            object("y", ObjectKind::Blob, 300, &[]),
+            object("x2", ObjectKind::Blob, 1100, &[]),
+        ]);
+        let hex = |name: &str| fake_oid(name).to_string();
+        let diff = |commit: &str, blobs: &[(&str, u64)]| CommitBlobs {
+            commit: hex(commit),
+            blobs: blobs.iter().map(|(b, size)| (hex(b), *size)).collect(),
+        };
+        let diffs = vec![
+            diff("c3", &[("x", 1000), ("base", 70)]),
+            diff("c2", &[("x2", 1100)]),
+            diff("c1", &[("x", 1000), ("y", 300)]),
+        ];
+
+        let (blobs, introduced_by) = attribute_commits(&[hex("c3")], &objects, &diffs, &[ObjectKind::Blob]);
+        let sizes: Vec<(String, u64)> = blobs.iter().map(|c| (c.commit.clone(), c.size)).collect();
+        assert_eq!(sizes, vec![(hex("c1"), 1300), (hex("c2"), 1100), (hex("c3"), 0)]);
+        assert!(blobs[0].readded.is_empty());
+        assert_eq!(
+            blobs[2].readded,
+            vec![
+                ReaddedBlob { blob: hex("base"), size: 70, first_commit: None },
+                ReaddedBlob { blob: hex("x"), size: 1000, first_commit: Some(hex("c1")) },
+            ]
+        );
+
+        // x is introduced by c1 even though c3 adds it again
+        assert_eq!(introduced_by[6], Some(2));
+        assert_eq!(introduced_by[8], Some(1));
+
+        let (everything, _) = attribute_commits(&[hex("c3")], &objects, &diffs, &ObjectKind::ALL);
+        let total: u64 = everything.iter().map(|c| c.size).sum();
+        assert_eq!(total, objects.iter().map(|o| o.size).sum::<u64>());
+        assert_eq!(everything[2].size, 260);
+        assert_eq!(everything[2].object_count, 2);
+    }
+
+    #[test]
+    fn test_top_files_per_branch() {
+        let mut blobs = HashMap::new();
+        blobs.insert("a".to_string(), HashMap::from([
+            ("shared".to_string(), 500u64),
+            ("only_a".to_string(), 100u64),
+            ("tiny_a".to_string(), 10u64),
+        ]));
+        blobs.insert("b".to_string(), HashMap::from([("shared".to_string(), 500u64), ("only_b".to_string(), 900u64)]));
+        let mock = MockGit {
+            branches: vec![("a".to_string(), "a".to_string()), ("b".to_string(), "b".to_string())],
+            blobs,
+            parents: HashMap::new(),
+        };
+
+        let analysis = analyze_branches(&mock, Path::new("/fake"), &AnalysisOptions::new()).unwrap();
+        let (details, errors) = analyze_branch_details(&mock, Path::new("/fake"), &analysis, 2, 2).unwrap();
+        assert!(errors.is_empty());
+
+        let a = details.iter().find(|d| d.branch == "a").unwrap();
+        let file = |name: &str, size: u64, shared: bool| FileWeight {
+            blob: fake_oid(name).to_string(),
+            path: Some(format!("files/{}", name)),
+            size,
+            commit: fake_oid("a").to_string(),
+            shared,
+        };
+        assert_eq!(a.top_files, vec![file("shared", 500, true), file("only_a", 100, false)]);
+
+        let b = details.iter().find(|d| d.branch == "b").unwrap();
+        assert_eq!(b.top_files[0].blob, fake_oid("only_b").to_string());
+        assert_eq!(b.top_files[0].commit, fake_oid("b").to_string());
+    }
+
+    #[test]
+    fn test_large_objects_list_branches_paths_and_introducing_commit() {
+        let mut blobs = HashMap::new();
+        blobs.insert("a".to_string(), HashMap::from([("dump".to_string(), 5000u64), ("small".to_string(), 10u64)]));
+        blobs.insert("b".to_string(), HashMap::from([("dump".to_string(), 5000u64), ("video".to_string(), 2000u64)]));
+        let mock = MockGit {
+            branches: vec![("a".to_string(), "a".to_string()), ("b".to_string(), "b".to_string())],
+            blobs,
+            parents: HashMap::new(),
+        };
+
+        // large blobs are found even when only commits are weighed
+        let options = AnalysisOptions::new().object_types([ObjectKind::Commit]);
+        let analysis = analyze_branches(&mock, Path::new("/fake"), &options).unwrap();
+        let large = find_large_objects(&mock, Path::new("/fake"), &analysis, 1000).unwrap();
+
+        let a = analysis.branch_index("a").unwrap();
+        let b = analysis.branch_index("b").unwrap();
+        assert_eq!(
+            large,
+            vec![
+                LargeObject {
+                    blob: fake_oid("dump").to_string(),
+                    size: 5000,
+                    branches: vec![a, b],
+                    paths: vec!["a/dump".to_string(), "b/dump".to_string()],
+                    commit: Some(fake_oid("a").to_string()),
+                    author: Some("Dev <a@example.com>".to_string()),
+                },
+                LargeObject {
+                    blob: fake_oid("video").to_string(),
+                    size: 2000,
+                    branches: vec![b],
+                    paths: vec!["b/video".to_string()],
+                    commit: Some(fake_oid("b").to_string()),
+                    author: Some("Dev <b@example.com>".to_string()),
+                },
+            ]
+        );
+        assert!(find_large_objects(&mock, Path::new("/fake"), &analysis, 6000).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_large_objects_credit_the_ancestor_despite_dates() {
+        // `child` sorts first, so the mock dates it before its grandparent, as
+        // after a rebase; it copies the dump to a second path. `middle` adds
+        // no blobs, so it never shows up among the changes.
+        let mut blobs = HashMap::new();
+        blobs.insert("parent".to_string(), HashMap::from([("dump".to_string(), 5000u64)]));
+        blobs.insert("child".to_string(), HashMap::from([("dump".to_string(), 5000u64)]));
+        let mut parents = HashMap::new();
+        parents.insert("middle".to_string(), vec!["parent".to_string()]);
+        parents.insert("child".to_string(), vec!["middle".to_string()]);
+        let mock = MockGit { branches: vec![("a".to_string(), "child".to_string())], blobs, parents };
+
+        let analysis = analyze_branches(&mock, Path::new("/fake"), &AnalysisOptions::new()).unwrap();
+        let large = find_large_objects(&mock, Path::new("/fake"), &analysis, 1000).unwrap();
+        assert_eq!(large.len(), 1);
+        assert_eq!(large[0].paths, ["child/dump", "parent/dump"]);
+        assert_eq!(large[0].commit, Some(fake_oid("parent").to_string()));
+        assert_eq!(large[0].author.as_deref(), Some("Dev <parent@example.com>"));
+    }
+
+    #[test]
+    fn test_sort_keys_and_age_limits() {
+        let mut blobs = HashMap::new();
+        blobs.insert("c".to_string(), HashMap::from([("c_only".to_string(), 500u64), ("shared".to_string(), 8000u64)]));
+        blobs.insert("a".to_string(), HashMap::from([("a_only".to_string(), 13000u64), ("shared".to_string(), 8000u64)]));
+        blobs.insert("b".to_string(), HashMap::from([("b_only".to_string(), 5000u64)]));
+        // c is 0 days old, a 30 and b 60
+        let mock = MockGit {
+            branches: vec![
+                ("c".to_string(), "c".to_string()),
+                ("a".to_string(), "a".to_string()),
+                ("b".to_string(), "b".to_string()),
+            ],
+            blobs,
+            parents: HashMap::new(),
+        };
+        let order = |options: AnalysisOptions| {
+            let analysis = analyze_branches(&mock, Path::new("/fake"), &options.as_of(MOCK_NOW)).unwrap();
+            analysis.weights.iter().map(|w| w.branch.clone()).collect::<Vec<_>>()
+        };
+
+        assert_eq!(order(AnalysisOptions::new()), ["a", "c", "b"]);
+        assert_eq!(order(AnalysisOptions::new().sort_by(SortKey::Unique)), ["a", "b", "c"]);
+        assert_eq!(order(AnalysisOptions::new().sort_by(SortKey::Age)), ["b", "a", "c"]);
+        // a: 17000 attributed bytes x 30 days beats b: 5000 x 60
+        assert_eq!(order(AnalysisOptions::new().sort_by(SortKey::Score)), ["a", "b", "c"]);
+
+        let day = Duration::from_secs(86400);
+        assert_eq!(order(AnalysisOptions::new().older_than(day * 45)), ["b"]);
+        assert_eq!(order(AnalysisOptions::new().newer_than(day * 45)), ["a", "c"]);
+        assert_eq!(order(AnalysisOptions::new().older_than(day * 20).newer_than(day * 40)), ["a"]);
+
+        // skipped branches still keep objects shared, and are never planned
+        let options = AnalysisOptions::new().older_than(day * 20).as_of(MOCK_NOW);
+        let analysis = analyze_branches(&mock, Path::new("/fake"), &options).unwrap();
+        let c = analysis.branch_index("c").unwrap() as usize;
+        assert_eq!(
+            analysis.statuses[c],
+            BranchStatus::Skipped("last commit 0 days ago, not older than 20 days".to_string())
+        );
+        let a = analysis.weights.iter().find(|w| w.branch == "a").unwrap();
+        assert_eq!((a.unique_size, a.shared_size), (13000, 8000));
+        let planned: Vec<u32> = analysis.plan_cleanup(PlanGoal::TopK(3)).iter().map(|s| s.branch).collect();
+        assert!(!planned.contains(&(c as u32)));
+        assert_eq!(planned.len(), 2);
+        // c's objects still count towards the footprint
+        let attributed: u64 = analysis.weights.iter().map(|w| w.attributed_size).sum();
+        assert_eq!(attributed, 22000);
+        assert_eq!(analysis.unmerged_size(), (26500, 8000));
+    }
+
+    #[test]
+    fn test_age_limits_skip_branches_of_unknown_age() {
+        let mut blobs = HashMap::new();
+        blobs.insert("a".to_string(), HashMap::from([("a_only".to_string(), 100u64)]));
+        blobs.insert("undated".to_string(), HashMap::from([("u_only".to_string(), 200u64)]));
+        let mock = MockGit {
+            branches: vec![("a".to_string(), "a".to_string()), ("u".to_string(), "undated".to_string())],
+            blobs,
+            parents: HashMap::new(),
+        };
+        let names = |options: AnalysisOptions| {
+            let analysis = analyze_branches(&mock, Path::new("/fake"), &options.as_of(MOCK_NOW)).unwrap();
+            let skipped = analysis.statuses[1].clone();
+            (analysis.weights.iter().map(|w| w.branch.clone()).collect::<Vec<_>>(), skipped)
+        };
+
+        assert_eq!(names(AnalysisOptions::new()), (vec!["u".to_string(), "a".to_string()], BranchStatus::Ok));
+        let unknown = BranchStatus::Skipped("unknown tip date".to_string());
+        let day = Duration::from_secs(86400);
+        assert_eq!(names(AnalysisOptions::new().newer_than(day)), (vec!["a".to_string()], unknown.clone()));
+        assert_eq!(names(AnalysisOptions::new().older_than(day)), (Vec::new(), unknown));
+    }
+
+    #[test]
+    fn test_filtered_branches_are_not_scanned() {
+        let mut blobs = HashMap::new();
+        blobs.insert("a".to_string(), HashMap::from([("shared".to_string(), 800u64), ("a_only".to_string(), 100u64)]));
+        blobs.insert("b".to_string(), HashMap::from([("shared".to_string(), 800u64)]));
+        blobs.insert("c".to_string(), HashMap::from([("c_only".to_string(), 50u64)]));
+        let mock = MockGit {
+            branches: vec![
+                ("feature/a".to_string(), "a".to_string()),
+                ("release/b".to_string(), "b".to_string()),
+                ("feature/c".to_string(), "c".to_string()),
+            ],
+            blobs,
+            parents: HashMap::new(),
+        };
+
+        let options = AnalysisOptions::new().include("feature/*").exclude("*/c");
+        let analysis = analyze_branches(&mock, Path::new("/fake"), &options).unwrap();
+        assert_eq!(analysis.branches.len(), 1);
+        // nothing else holds `shared` any more
+        let a = &analysis.weights[0];
+        assert_eq!((a.branch.as_str(), a.unique_size, a.shared_size), ("feature/a", 900, 0));
+
+        let none = analyze_branches(&mock, Path::new("/fake"), &AnalysisOptions::new().remote("origin")).unwrap();
+        assert!(none.branches.is_empty() && none.weights.is_empty());
+    }
+
+    #[test]
+    fn test_group_upstreams() {
+        let mut blobs = HashMap::new();
+        blobs.insert("c1".to_string(), HashMap::from([("base".to_string(), 1000u64)]));
+        blobs.insert("l2".to_string(), HashMap::from([("local".to_string(), 100u64), ("common".to_string(), 50u64)]));
+        blobs.insert("l3".to_string(), HashMap::new());
+        blobs.insert("r2".to_string(), HashMap::from([("remote".to_string(), 10u64)]));
+        blobs.insert("o1".to_string(), HashMap::from([("common".to_string(), 50u64)]));
+        let mut parents = HashMap::new();
+        parents.insert("l2".to_string(), vec!["c1".to_string()]);
+        parents.insert("l3".to_string(), vec!["l2".to_string()]);
+        parents.insert("r2".to_string(), vec!["c1".to_string()]);
+        let mock = MockGit {
+            branches: vec![
+                ("x".to_string(), "l3".to_string()),
+                ("origin/x".to_string(), "r2".to_string()),
+                ("other".to_string(), "o1".to_string()),
+            ],
+            blobs,
+            parents,
+        };
+
+        let separate = analyze_branches(&mock, Path::new("/fake"), &AnalysisOptions::new()).unwrap();
+        let x = separate.weights.iter().find(|w| w.branch == "x").unwrap();
+        assert_eq!((x.unique_size, x.shared_size, x.upstream.clone()), (100, 1050, None));
+        assert_eq!(separate.weights.len(), 3);
+
+        let grouped =
+            analyze_branches(&mock, Path::new("/fake"), &AnalysisOptions::new().group_upstreams(true)).unwrap();
+        assert_eq!(grouped.weights.len(), 2);
+        let x = grouped.weights.iter().find(|w| w.branch == "x").unwrap();
+        assert_eq!((x.unique_size, x.shared_size), (1110, 50));
+        let upstream = x.upstream.as_ref().unwrap();
+        assert_eq!((upstream.branch.as_str(), upstream.ahead, upstream.behind), ("origin/x", 2, 1));
+        assert_eq!(upstream.tip, fake_oid("r2").to_string());
+        assert_eq!(grouped.statuses[1], BranchStatus::Skipped("grouped with x".to_string()));
+        // the other branch still shares `common` with the group
+        let other = grouped.weights.iter().find(|w| w.branch == "other").unwrap();
+        assert_eq!((other.unique_size, other.shared_size), (0, 50));
+
+        // deleting the upstream means deleting the pair
+        let local = grouped.branch_index("x").unwrap();
+        assert_eq!(grouped.branch_index("origin/x"), Some(local));
+        let reclaim = grouped.reclaimable_if_deleted(&[grouped.branch_index("origin/x").unwrap()]);
+        assert_eq!((reclaim.size, reclaim.marginal.clone()), (1110, vec![(local, 1110)]));
+        // and naming both sides still counts the pair once
+        let both: Vec<u32> = ["x", "refs/remotes/origin/x"].iter().map(|n| grouped.branch_index(n).unwrap()).collect();
+        assert_eq!(grouped.reclaimable_if_deleted(&both), reclaim);
+        assert_eq!(separate.branch_index("origin/x"), Some(1));
+    }
+
+    #[test]
+    fn test_path_labels() {
+        assert_eq!(top_directory("vendor/lib/x.c"), "vendor/");
+        assert_eq!(top_directory("README"), "(root)");
+        assert_eq!(extension("assets/Logo.PSD"), ".psd");
+        assert_eq!(extension("dir.d/Makefile"), "(none)");
+        assert_eq!(extension(".gitignore"), "(none)");
+        assert_eq!(extension("archive.tar.gz"), ".gz");
+        assert_eq!(extension("trailing."), "(none)");
+    }
+
+    #[test]
+    fn test_path_breakdown_per_branch_and_rollup() {
+        let mut blobs = HashMap::new();
+        blobs.insert("a".to_string(), HashMap::from([("logo.psd".to_string(), 500u64), ("a.bin".to_string(), 100u64)]));
+        blobs.insert("b".to_string(), HashMap::from([("logo.psd".to_string(), 500u64), ("b.psd".to_string(), 300u64)]));
+        let mock = MockGit {
+            branches: vec![("a".to_string(), "a".to_string()), ("b".to_string(), "b".to_string())],
+            blobs,
+            parents: HashMap::new(),
+        };
+
+        let plain = analyze_branches(&mock, Path::new("/fake"), &AnalysisOptions::new()).unwrap();
+        assert!(plain.paths.is_none());
+        assert!(plain.weights.iter().all(|w| w.paths.is_none()));
+
+        let analysis = analyze_branches(&mock, Path::new("/fake"), &AnalysisOptions::new().path_breakdown(true)).unwrap();
+        let stats = |size: u64, count: usize| KindStats { size, count };
+        let a = analysis.weights.iter().find(|w| w.branch == "a").unwrap().paths.clone().unwrap();
+        assert_eq!(a.directories, vec![("files/".to_string(), stats(600, 2))]);
+        assert_eq!(a.extensions, vec![(".psd".to_string(), stats(500, 1)), (".bin".to_string(), stats(100, 1))]);
+
+        // the shared logo counts once across branches
+        let all = analysis.paths.unwrap();
+        assert_eq!(all.directories, vec![("files/".to_string(), stats(900, 3))]);
+        assert_eq!(all.extensions, vec![(".psd".to_string(), stats(800, 2)), (".bin".to_string(), stats(100, 1))]);
+    }
+
+    #[test]
+    fn test_lfs_content_weighed_apart_from_pointers() {
+        let repo = std::env::temp_dir().join(format!("branch-weight-lfs-mock-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&repo);
+        let fetched = lfs_oid("lfs_shared");
+        let dir = repo.join(".git/lfs/objects").join(&fetched[..2]).join(&fetched[2..4]);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join(&fetched), vec![0u8; 1200]).unwrap();
+
+        let mut blobs = HashMap::new();
+        blobs.insert("a".to_string(), HashMap::from([
+            ("lfs_shared".to_string(), 120u64),
+            ("lfs_a".to_string(), 130u64),
+            ("code".to_string(), 50u64),
+        ]));
+        blobs.insert("b".to_string(), HashMap::from([("lfs_shared".to_string(), 120u64)]));
+        let mock = MockGit {
+            branches: vec![("a".to_string(), "a".to_string()), ("b".to_string(), "b".to_string())],
+            blobs,
+            parents: HashMap::new(),
+        };
+
+        let plain = analyze_branches(&mock, &repo, &AnalysisOptions::new()).unwrap();
+        assert!(plain.lfs.is_none() && plain.weights[0].lfs.is_none());
+
+        let analysis = analyze_branches(&mock, &repo, &AnalysisOptions::new().lfs(true)).unwrap();
+        let a = analysis.weights.iter().find(|w| w.branch == "a").unwrap();
+        // pointers still count as git objects, the content comes on top
+        assert_eq!(a.total_size, 300);
+        assert_eq!(a.lfs, Some(LfsStats { size: 2500, local_size: 1200, count: 2, missing_count: 1 }));
+        let b = analysis.weights.iter().find(|w| w.branch == "b").unwrap();
+        assert_eq!(b.lfs, Some(LfsStats { size: 1200, local_size: 1200, count: 1, missing_count: 0 }));
+        assert_eq!(analysis.lfs, Some(LfsStats { size: 2500, local_size: 1200, count: 2, missing_count: 1 }));
+
+        let _ = std::fs::remove_dir_all(&repo);
+    }
+
+    // Fails any walk that includes `bad_tip`, like a branch with a missing object.
+    struct FailingGit {
+        inner: MockGit,
+        bad_tip: String,
+    }
+
+    impl GitOps for FailingGit {
+        fn list_refs(&self, repo: &Path) -> Result<Vec<(String, String)>> {
+            self.inner.list_refs(repo)
+        }
+
+        fn get_branches(&self, repo: &Path, bases: &[String]) -> Result<(Vec<BranchRef>, Vec<BrokenRef>)> {
+            let (branches, _) = self.inner.get_branches(repo, bases)?;
+            let broken = vec![BrokenRef { refname: "refs/heads/corrupt".to_string(), reason: "ignoring broken ref".to_string() }];
+            Ok((branches, broken))
+        }
+
+        fn get_unmerged_objects(
+            &self,
+            repo: &Path,
+            tips: &[String],
+            exclude: &[String],
+            delta_info: bool,
+        ) -> Result<UnmergedObjects> {
+            if tips.contains(&fake_oid(&self.bad_tip).to_string()) {
+                return Err(crate::error::BranchWeightError::missing_object(&self.bad_tip));
+            }
+            self.inner.get_unmerged_objects(repo, tips, exclude, delta_info)
+        }
+
+        fn get_unmerged_commits(&self, repo: &Path, branch: &str, exclude: &[String]) -> Result<Vec<crate::git::CommitBlobs>> {
+            self.inner.get_unmerged_commits(repo, branch, exclude)
+        }
+
+        fn get_small_blobs(&self, repo: &Path, oids: &[Oid], max_size: u64) -> Result<Vec<(Oid, Vec<u8>)>> {
+            self.inner.get_small_blobs(repo, oids, max_size)
+        }
+
+        fn find_blob_changes(&self, repo: &Path, tips: &[String], exclude: &[String], blobs: &[Oid]) -> Result<Vec<BlobChange>> {
+            self.inner.find_blob_changes(repo, tips, exclude, blobs)
+        }
+
+        fn resolve_commit(&self, repo: &Path, rev: &str) -> Result<Option<String>> {
+            self.inner.resolve_commit(repo, rev)
+        }
+
+        fn git_dir(&self, repo: &Path) -> Result<PathBuf> {
+            self.inner.git_dir(repo)
+        }
+    }
+
+    #[test]
+    fn test_failed_and_skipped_branches_get_a_status() {
+        let mut blobs = HashMap::new();
+        blobs.insert("good".to_string(), HashMap::from([("o1".to_string(), 100u64)]));
+        blobs.insert("empty".to_string(), HashMap::new());
+
+        let git = FailingGit {
+            inner: MockGit {
+                branches: vec![
+                    ("feature/bad".to_string(), "bad".to_string()),
+                    ("feature/empty".to_string(), "empty".to_string()),
+                    ("feature/good".to_string(), "good".to_string()),
+                ],
+                blobs,
+                parents: HashMap::new(),
+            },
+            bad_tip: "bad".to_string(),
+        };
+
+        let analysis = analyze_branches(&git, Path::new("/fake"), &AnalysisOptions::new()).unwrap();
+
+        assert_eq!(analysis.weights.len(), 1);
+        assert_eq!(analysis.weights[0].branch, "feature/good");
+        assert_eq!(analysis.statuses[0], BranchStatus::Failed("Object bad is unreadable: object is missing".to_string()));
+        assert!(matches!(analysis.statuses[1], BranchStatus::Skipped(_)));
+        assert_eq!(analysis.statuses[2], BranchStatus::Ok);
+        assert_eq!(analysis.branches[3].refname, "refs/heads/corrupt");
+        assert!(matches!(analysis.statuses[3], BranchStatus::Failed(_)));
+
+        let failures = analysis.failures();
+        assert_eq!(failures.len(), 2);
+        assert_eq!(failures[0].branch, "feature/bad");
+        assert_eq!(failures[0].stage, "scan");
+    }
 }
@@ -0,0 +1,173 @@
+use crate::error::{BranchWeightError, Result};
+use crate::git::GitOps;
+use std::path::Path;
+
+// Shell-style matching where `*` matches any run of characters, slashes
+// included, and `?` matches a single character.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.as_bytes();
+    let text = text.as_bytes();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == b'?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    t = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+pub fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?'])
+}
+
+// `refs/heads/x` -> `x`, `refs/remotes/origin/x` -> `origin/x`
+pub fn short_name(refname: &str) -> &str {
+    refname
+        .strip_prefix("refs/heads/")
+        .or_else(|| refname.strip_prefix("refs/remotes/"))
+        .unwrap_or(refname)
+}
+
+// Which branches get scanned at all. Patterns match like `--base` globs;
+// a branch is kept if it belongs to `remote` (when set), matches one of
+// `include` (when any) and none of `exclude`.
+#[derive(Debug, Clone, Default)]
+pub struct RefFilter {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    pub remote: Option<String>,
+}
+
+impl RefFilter {
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty() && self.remote.is_none()
+    }
+
+    pub fn matches(&self, refname: &str) -> bool {
+        if let Some(remote) = &self.remote {
+            let in_remote = refname
+                .strip_prefix("refs/remotes/")
+                .and_then(|rest| rest.strip_prefix(remote.as_str()))
+                .is_some_and(|rest| rest.starts_with('/'));
+            if !in_remote {
+                return false;
+            }
+        }
+        let matching = |pattern: &String| pattern_matches(pattern, refname);
+        (self.include.is_empty() || self.include.iter().any(matching)) && !self.exclude.iter().any(matching)
+    }
+}
+
+// Globs starting with `refs/` are matched against the full refname, others
+// against the short branch name.
+fn pattern_matches(pattern: &str, refname: &str) -> bool {
+    let candidate = if pattern.starts_with("refs/") { refname } else { short_name(refname) };
+    glob_match(pattern, candidate)
+}
+
+// Expands `--base` patterns into the refs to exclude. Plain names must resolve
+// to a commit and are passed through for git to resolve; globs are matched
+// against the short branch name (`release/*`, `origin/release/*`) or, when
+// they start with `refs/`, against the full refname.
+pub fn resolve_bases<G: GitOps>(git: &G, repo: &Path, patterns: &[String]) -> Result<Vec<String>> {
+    let mut bases = Vec::new();
+    let mut refs = None;
+
+    for pattern in patterns {
+        if !is_glob(pattern) {
+            if !git.rev_exists(repo, pattern)? {
+                return Err(BranchWeightError::DefaultBranchNotFound(pattern.clone()));
+            }
+            bases.push(pattern.clone());
+            continue;
+        }
+
+        let refs: &Vec<(String, String)> = match &mut refs {
+            Some(refs) => refs,
+            None => refs.insert(git.list_refs(repo)?),
+        };
+        let matched: Vec<String> = refs
+            .iter()
+            .filter(|(refname, _)| pattern_matches(pattern, refname))
+            .map(|(refname, _)| refname.clone())
+            .collect();
+
+        if matched.is_empty() {
+            println!("Warning: base pattern '{}' matched no branches", pattern);
+        }
+        bases.extend(matched);
+    }
+
+    bases.sort();
+    bases.dedup();
+    if bases.is_empty() {
+        return Err(BranchWeightError::DefaultBranchNotFound(patterns.join(", ")));
+    }
+    Ok(bases)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("release/*", "release/1.0"));
+        assert!(glob_match("release/*", "release/2.x/hotfix"));
+        assert!(!glob_match("release/*", "origin/release/1.0"));
+        assert!(glob_match("*/release/*", "origin/release/1.0"));
+        assert!(glob_match("v?.?", "v1.2"));
+        assert!(!glob_match("v?.?", "v1.23"));
+        assert!(glob_match("main", "main"));
+        assert!(!glob_match("main", "maint"));
+        assert!(glob_match("*", ""));
+    }
+
+    #[test]
+    fn test_ref_filter() {
+        let filter = RefFilter {
+            include: vec!["origin/feature/*".to_string(), "refs/heads/wip/*".to_string()],
+            exclude: vec!["*/feature/legacy-*".to_string()],
+            remote: None,
+        };
+        assert!(filter.matches("refs/remotes/origin/feature/a"));
+        assert!(!filter.matches("refs/remotes/origin/feature/legacy-ui"));
+        assert!(filter.matches("refs/heads/wip/x"));
+        assert!(!filter.matches("refs/heads/feature/a"));
+
+        let origin = RefFilter { remote: Some("origin".to_string()), ..RefFilter::default() };
+        assert!(origin.matches("refs/remotes/origin/main"));
+        assert!(!origin.matches("refs/remotes/origin-old/main"));
+        assert!(!origin.matches("refs/heads/origin/main"));
+
+        let exclude_only = RefFilter { exclude: vec!["release/*".to_string()], ..RefFilter::default() };
+        assert!(exclude_only.matches("refs/heads/feature/a"));
+        assert!(!exclude_only.matches("refs/heads/release/1.0"));
+        assert!(RefFilter::default().is_empty());
+    }
+
+    #[test]
+    fn test_short_name() {
+        assert_eq!(short_name("refs/heads/feature/x"), "feature/x");
+        assert_eq!(short_name("refs/remotes/origin/main"), "origin/main");
+        assert_eq!(short_name("refs/tags/v1"), "refs/tags/v1");
+    }
+}
@@ -1,12 +1,20 @@
-use crate::objects::{BranchDetail, BranchWeight};
-use anyhow::Result;
+use crate::git::{CommitInfo, ObjectKind, RefKind};
+use crate::objects::{
+    Analysis, BranchDetail, BranchError, BranchWeight, KindStats, LargeObject, LfsStats, PathBreakdown, PlanGoal, PlanStep,
+    Reclaim, Upstream,
+};
+use crate::error::{BranchWeightError, Result};
 use serde::Serialize;
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::Path;
 
 #[derive(Serialize)]
 struct BranchReport {
     branch: String,
+    refname: String,
+    kind: &'static str,
+    tip: String,
     #[serde(rename = "totalSizeMB")]
     total_size_mb: String,
     #[serde(rename = "uniqueSizeMB")]
@@ -19,29 +27,214 @@ struct BranchReport {
     unique_size: u64,
     #[serde(rename = "sharedSize")]
     shared_size: u64,
+    #[serde(rename = "attributedSizeMB")]
+    attributed_size_mb: String,
+    #[serde(rename = "attributedSize")]
+    attributed_size: u64,
+    // bytes freed by deleting this branch alone, which are its unique bytes
+    #[serde(rename = "marginalSizeMB")]
+    marginal_size_mb: String,
+    #[serde(rename = "marginalSize")]
+    marginal_size: u64,
     #[serde(rename = "objectCount")]
     object_count: usize,
     #[serde(rename = "uniqueObjectCount")]
     unique_object_count: usize,
     #[serde(rename = "sharedObjectCount")]
     shared_object_count: usize,
+    #[serde(rename = "byType")]
+    by_type: BTreeMap<&'static str, KindReport>,
+    #[serde(flatten)]
+    delta: Option<DeltaReport>,
+    #[serde(flatten)]
+    paths: Option<PathsReport>,
+    #[serde(flatten)]
+    lfs: Option<LfsReport>,
+    #[serde(flatten)]
+    last_commit: Option<LastCommitReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    score: Option<f64>,
+    #[serde(flatten)]
+    upstream: Option<UpstreamReport>,
+}
+
+#[derive(Serialize)]
+struct LastCommitReport {
+    #[serde(rename = "lastCommitDate")]
+    date: String,
+    #[serde(rename = "ageDays")]
+    age_days: u64,
+    author: String,
+    #[serde(rename = "authorEmail")]
+    author_email: String,
+    subject: String,
+}
+
+impl LastCommitReport {
+    fn new(info: &CommitInfo, now: i64) -> Self {
+        LastCommitReport {
+            date: format_date(info.date),
+            age_days: info.age_days(now),
+            author: info.author_name.clone(),
+            author_email: info.author_email.clone(),
+            subject: info.subject.clone(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct UpstreamReport {
+    upstream: String,
+    #[serde(rename = "upstreamRefname")]
+    refname: String,
+    #[serde(rename = "upstreamTip")]
+    tip: String,
+    #[serde(rename = "aheadCount")]
+    ahead: usize,
+    #[serde(rename = "behindCount")]
+    behind: usize,
+}
+
+impl UpstreamReport {
+    fn new(upstream: &Upstream) -> Self {
+        UpstreamReport {
+            upstream: upstream.branch.clone(),
+            refname: upstream.refname.clone(),
+            tip: upstream.tip.clone(),
+            ahead: upstream.ahead,
+            behind: upstream.behind,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct UpstreamReportLight {
+    upstream: String,
+    #[serde(rename = "aheadCount")]
+    ahead: usize,
+    #[serde(rename = "behindCount")]
+    behind: usize,
+}
+
+#[derive(Serialize)]
+struct DeltaReport {
+    #[serde(rename = "rawSizeMB")]
+    raw_size_mb: String,
+    #[serde(rename = "rawSize")]
+    raw_size: u64,
+    #[serde(rename = "standaloneSizeMB")]
+    standalone_size_mb: String,
+    #[serde(rename = "standaloneSize")]
+    standalone_size: u64,
+    #[serde(rename = "externalDeltaCount")]
+    external_delta_count: usize,
+}
+
+#[derive(Serialize)]
+struct LfsReport {
+    #[serde(rename = "lfsSizeMB")]
+    size_mb: String,
+    #[serde(rename = "lfsSize")]
+    size: u64,
+    #[serde(rename = "lfsLocalSize")]
+    local_size: u64,
+    #[serde(rename = "lfsObjectCount")]
+    count: usize,
+    #[serde(rename = "lfsMissingCount")]
+    missing_count: usize,
+}
+
+impl LfsReport {
+    fn new(stats: &LfsStats) -> Self {
+        LfsReport {
+            size_mb: format_size_mb(stats.size),
+            size: stats.size,
+            local_size: stats.local_size,
+            count: stats.count,
+            missing_count: stats.missing_count,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct PathsReport {
+    #[serde(rename = "byDirectory")]
+    by_directory: Vec<PathReport>,
+    #[serde(rename = "byExtension")]
+    by_extension: Vec<PathReport>,
+}
+
+#[derive(Serialize)]
+struct PathReport {
+    name: String,
+    #[serde(rename = "sizeMB")]
+    size_mb: String,
+    size: u64,
+    count: usize,
+}
+
+impl PathsReport {
+    fn new(paths: &PathBreakdown) -> Self {
+        let entries = |totals: &[(String, KindStats)]| {
+            totals
+                .iter()
+                .map(|(name, stats)| PathReport {
+                    name: name.clone(),
+                    size_mb: format_size_mb(stats.size),
+                    size: stats.size,
+                    count: stats.count,
+                })
+                .collect()
+        };
+        PathsReport {
+            by_directory: entries(&paths.directories),
+            by_extension: entries(&paths.extensions),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct KindReport {
+    #[serde(rename = "sizeMB")]
+    size_mb: String,
+    size: u64,
+    count: usize,
 }
 
 #[derive(Serialize)]
 struct BranchReportLight {
     branch: String,
+    kind: &'static str,
     #[serde(rename = "totalSizeMB")]
     total_size_mb: String,
     #[serde(rename = "uniqueSizeMB")]
     unique_size_mb: String,
     #[serde(rename = "sharedSizeMB")]
     shared_size_mb: String,
+    #[serde(rename = "attributedSizeMB")]
+    attributed_size_mb: String,
+    #[serde(flatten)]
+    last_commit: Option<LastCommitReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    score: Option<f64>,
+    #[serde(flatten)]
+    upstream: Option<UpstreamReportLight>,
 }
 
 #[derive(Serialize)]
 struct Summary {
     #[serde(rename = "totalBranches")]
     total_branches: usize,
+    #[serde(rename = "localBranches")]
+    local_branches: usize,
+    #[serde(rename = "remoteBranches")]
+    remote_branches: usize,
+    #[serde(rename = "scannedBranches")]
+    scanned_branches: usize,
+    #[serde(rename = "failedBranches")]
+    failed_branches: usize,
+    #[serde(rename = "skippedBranches")]
+    skipped_branches: usize,
     #[serde(rename = "totalUniqueSize")]
     total_unique_size: u64,
     #[serde(rename = "totalUniqueSizeMB")]
@@ -50,22 +243,73 @@ struct Summary {
     total_shared_size: u64,
     #[serde(rename = "totalSharedSizeMB")]
     total_shared_size_mb: String,
+    #[serde(rename = "deduplicatedSharedSize")]
+    deduplicated_shared_size: u64,
+    #[serde(rename = "deduplicatedSharedSizeMB")]
+    deduplicated_shared_size_mb: String,
+    #[serde(rename = "totalUnmergedSize")]
+    total_unmerged_size: u64,
+    #[serde(rename = "totalUnmergedSizeMB")]
+    total_unmerged_size_mb: String,
+    #[serde(rename = "peakMemoryMB", skip_serializing_if = "Option::is_none")]
+    peak_memory_mb: Option<String>,
+    #[serde(rename = "cacheHits", skip_serializing_if = "Option::is_none")]
+    cache_hits: Option<usize>,
+    #[serde(rename = "cacheMisses", skip_serializing_if = "Option::is_none")]
+    cache_misses: Option<usize>,
+    #[serde(flatten)]
+    paths: Option<PathsReport>,
+    #[serde(flatten)]
+    lfs: Option<LfsReport>,
 }
 
-pub fn write_reports(out_dir: &Path, branches: &[BranchWeight]) -> Result<()> {
+pub fn write_reports(out_dir: &Path, analysis: &Analysis) -> Result<()> {
+    let branches = &analysis.weights;
+    let now = analysis.as_of;
     let full_reports: Vec<BranchReport> = branches
         .iter()
         .map(|b| BranchReport {
             branch: b.branch.clone(),
+            refname: b.refname.clone(),
+            kind: b.kind().as_str(),
+            tip: b.tip.clone(),
             total_size_mb: format_size_mb(b.total_size),
             unique_size_mb: format_size_mb(b.unique_size),
             shared_size_mb: format_size_mb(b.shared_size),
             total_size: b.total_size,
             unique_size: b.unique_size,
             shared_size: b.shared_size,
+            attributed_size_mb: format_size_mb(b.attributed_size),
+            attributed_size: b.attributed_size,
+            marginal_size_mb: format_size_mb(b.unique_size),
+            marginal_size: b.unique_size,
             object_count: b.object_count,
             unique_object_count: b.unique_count,
             shared_object_count: b.shared_count,
+            by_type: ObjectKind::ALL
+                .iter()
+                .map(|&kind| {
+                    let stats = b.by_kind.get(kind);
+                    let report = KindReport {
+                        size_mb: format_size_mb(stats.size),
+                        size: stats.size,
+                        count: stats.count,
+                    };
+                    (kind.as_str(), report)
+                })
+                .collect(),
+            delta: b.delta.map(|d| DeltaReport {
+                raw_size_mb: format_size_mb(d.raw_size),
+                raw_size: d.raw_size,
+                standalone_size_mb: format_size_mb(d.standalone_size),
+                standalone_size: d.standalone_size,
+                external_delta_count: d.external_delta_count,
+            }),
+            paths: b.paths.as_ref().map(PathsReport::new),
+            lfs: b.lfs.as_ref().map(LfsReport::new),
+            last_commit: b.last_commit.as_ref().map(|info| LastCommitReport::new(info, now)),
+            score: score(b, now),
+            upstream: b.upstream.as_ref().map(UpstreamReport::new),
         })
         .collect();
 
@@ -73,39 +317,167 @@ pub fn write_reports(out_dir: &Path, branches: &[BranchWeight]) -> Result<()> {
         .iter()
         .map(|b| BranchReportLight {
             branch: b.branch.clone(),
+            kind: b.kind().as_str(),
             total_size_mb: format_size_mb(b.total_size),
             unique_size_mb: format_size_mb(b.unique_size),
             shared_size_mb: format_size_mb(b.shared_size),
+            attributed_size_mb: format_size_mb(b.attributed_size),
+            last_commit: b.last_commit.as_ref().map(|info| LastCommitReport::new(info, now)),
+            score: score(b, now),
+            upstream: b.upstream.as_ref().map(|u| UpstreamReportLight {
+                upstream: u.branch.clone(),
+                ahead: u.ahead,
+                behind: u.behind,
+            }),
         })
         .collect();
 
     let total_unique: u64 = branches.iter().map(|b| b.unique_size).sum();
     let total_shared: u64 = branches.iter().map(|b| b.shared_size).sum();
+    // `totalSharedSize` counts a shared object once per branch holding it;
+    // these count it once, including objects of branches skipped by filters.
+    let (total_unmerged, deduplicated_shared) = analysis.unmerged_size();
+
+    let peak_memory = peak_memory_bytes();
+    let local_branches = branches.iter().filter(|b| b.kind() == RefKind::Local).count();
 
     let summary = Summary {
         total_branches: branches.len(),
+        local_branches,
+        remote_branches: branches.len() - local_branches,
+        scanned_branches: analysis.branches.len(),
+        failed_branches: analysis.count("failed"),
+        skipped_branches: analysis.count("skipped"),
         total_unique_size: total_unique,
         total_unique_size_mb: format_size_mb(total_unique),
         total_shared_size: total_shared,
         total_shared_size_mb: format_size_mb(total_shared),
+        deduplicated_shared_size: deduplicated_shared,
+        deduplicated_shared_size_mb: format_size_mb(deduplicated_shared),
+        total_unmerged_size: total_unmerged,
+        total_unmerged_size_mb: format_size_mb(total_unmerged),
+        peak_memory_mb: peak_memory.map(format_size_mb),
+        cache_hits: analysis.cache.map(|c| c.hits),
+        cache_misses: analysis.cache.map(|c| c.misses),
+        paths: analysis.paths.as_ref().map(PathsReport::new),
+        lfs: analysis.lfs.as_ref().map(LfsReport::new),
     };
 
     let full_path = out_dir.join("branches_full.json");
     let light_path = out_dir.join("branches.json");
     let summary_path = out_dir.join("summary.json");
 
-    fs::write(&full_path, serde_json::to_string_pretty(&full_reports)?)?;
-    fs::write(&light_path, serde_json::to_string_pretty(&light_reports)?)?;
-    fs::write(&summary_path, serde_json::to_string_pretty(&summary)?)?;
+    write_json(&full_path, &full_reports)?;
+    write_json(&light_path, &light_reports)?;
+    write_json(&summary_path, &summary)?;
 
     println!("Summary:");
-    println!("  Branches: {}", branches.len());
+    println!(
+        "  Branches: {} ({} local, {} remote)",
+        branches.len(),
+        local_branches,
+        branches.len() - local_branches
+    );
     println!("  Total unique size: {}", format_size_mb(total_unique));
     println!("  Total shared size: {}", format_size_mb(total_shared));
+    println!("  Total unmerged size (deduplicated): {}", format_size_mb(total_unmerged));
+    if summary.failed_branches > 0 || summary.skipped_branches > 0 {
+        println!(
+            "  Failed: {}, skipped: {} (of {} scanned)",
+            summary.failed_branches, summary.skipped_branches, summary.scanned_branches
+        );
+    }
+    if let Some(lfs) = &analysis.lfs {
+        println!(
+            "  LFS content: {} in {} objects ({} not in local storage)",
+            format_size_mb(lfs.size),
+            lfs.count,
+            lfs.missing_count
+        );
+    }
+    if let Some(paths) = &analysis.paths {
+        let largest = |totals: &[(String, KindStats)]| {
+            let top: Vec<String> = totals
+                .iter()
+                .take(3)
+                .map(|(name, stats)| format!("{} {}", name, format_size_mb(stats.size)))
+                .collect();
+            top.join(", ")
+        };
+        println!("  Largest directories: {}", largest(&paths.directories));
+        println!("  Largest extensions: {}", largest(&paths.extensions));
+    }
+    if let Some(peak) = peak_memory {
+        println!("  Peak memory: {}", format_size_mb(peak));
+    }
 
     Ok(())
 }
 
+fn write_json(path: &Path, value: &impl Serialize) -> Result<()> {
+    let json = serde_json::to_string_pretty(value).map_err(anyhow::Error::from)?;
+    fs::write(path, json).map_err(|source| BranchWeightError::OutputNotWritable {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+// Peak resident set size of this process so far, if the platform reports it.
+#[cfg(unix)]
+fn peak_memory_bytes() -> Option<u64> {
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    if unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) } != 0 {
+        return None;
+    }
+    let max_rss = usage.ru_maxrss as u64;
+    // Linux reports kilobytes, macOS bytes
+    if cfg!(target_os = "macos") {
+        Some(max_rss)
+    } else {
+        Some(max_rss * 1024)
+    }
+}
+
+#[cfg(not(unix))]
+fn peak_memory_bytes() -> Option<u64> {
+    None
+}
+
+fn last_commit(analysis: &Analysis, branch: u32, now: i64) -> Option<LastCommitReport> {
+    let info = analysis.branches[branch as usize].last_commit.as_ref()?;
+    Some(LastCommitReport::new(info, now))
+}
+
+// Rounded to one decimal; only known when the tip's date is.
+fn score(weight: &BranchWeight, now: i64) -> Option<f64> {
+    weight.last_commit.as_ref()?;
+    Some((weight.score(now) * 10.0).round() / 10.0)
+}
+
+// RFC 3339 in UTC, e.g. 2024-03-09T17:04:11Z.
+fn format_date(timestamp: i64) -> String {
+    let (days, secs) = (timestamp.div_euclid(86400), timestamp.rem_euclid(86400));
+    // civil-from-days, with eras of 400 years starting on March 1st
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
 fn format_size_mb(size: u64) -> String {
     let mb = size as f64 / (1024.0 * 1024.0);
     if mb >= 0.1 {
@@ -123,15 +495,31 @@ struct CommitReport {
     #[serde(rename = "sizeMB")]
     size_mb: String,
     size: u64,
+    #[serde(rename = "objectCount")]
+    object_count: usize,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    readded: Vec<ReaddedReport>,
+}
+
+#[derive(Serialize)]
+struct ReaddedReport {
+    blob: String,
+    size: u64,
+    #[serde(rename = "firstCommit")]
+    first_commit: Option<String>,
 }
 
 #[derive(Serialize)]
 struct BranchWithCommits {
     branch: String,
+    refname: String,
+    kind: &'static str,
     #[serde(rename = "totalSizeMB")]
     total_size_mb: String,
     #[serde(rename = "totalSize")]
     total_size: u64,
+    #[serde(flatten)]
+    last_commit: Option<LastCommitReport>,
     commits: Vec<CommitReport>,
 }
 
@@ -145,22 +533,297 @@ pub fn write_detailed_report(out_dir: &Path, details: &[BranchDetail]) -> Result
                     commit: c.commit.clone(),
                     size_mb: format_size_mb(c.size),
                     size: c.size,
+                    object_count: c.object_count,
+                    readded: c
+                        .readded
+                        .iter()
+                        .map(|r| ReaddedReport {
+                            blob: r.blob.clone(),
+                            size: r.size,
+                            first_commit: r.first_commit.clone(),
+                        })
+                        .collect(),
                 })
                 .collect();
-            commits.sort_by(|a, b| b.size.cmp(&a.size));
+            commits.sort_by_key(|c| std::cmp::Reverse(c.size));
 
             BranchWithCommits {
                 branch: d.branch.clone(),
+                refname: d.refname.clone(),
+                kind: RefKind::of(&d.refname).as_str(),
                 total_size_mb: format_size_mb(d.total_size),
                 total_size: d.total_size,
+                last_commit: d.last_commit.as_ref().map(|info| LastCommitReport::new(info, d.as_of)),
                 commits,
             }
         })
         .collect();
 
     let path = out_dir.join("branches_with_commits.json");
-    fs::write(&path, serde_json::to_string_pretty(&reports)?)?;
+    write_json(&path, &reports)?;
 
     println!("Detailed report with commits saved");
     Ok(())
 }
+
+#[derive(Serialize)]
+struct FileReport {
+    path: Option<String>,
+    blob: String,
+    #[serde(rename = "sizeMB")]
+    size_mb: String,
+    size: u64,
+    commit: String,
+    shared: bool,
+}
+
+#[derive(Serialize)]
+struct BranchFiles {
+    branch: String,
+    refname: String,
+    kind: &'static str,
+    #[serde(flatten)]
+    last_commit: Option<LastCommitReport>,
+    files: Vec<FileReport>,
+}
+
+pub fn write_top_files(out_dir: &Path, details: &[BranchDetail]) -> Result<()> {
+    let reports: Vec<BranchFiles> = details
+        .iter()
+        .map(|d| BranchFiles {
+            branch: d.branch.clone(),
+            refname: d.refname.clone(),
+            kind: RefKind::of(&d.refname).as_str(),
+            last_commit: d.last_commit.as_ref().map(|info| LastCommitReport::new(info, d.as_of)),
+            files: d
+                .top_files
+                .iter()
+                .map(|f| FileReport {
+                    path: f.path.clone(),
+                    blob: f.blob.clone(),
+                    size_mb: format_size_mb(f.size),
+                    size: f.size,
+                    commit: f.commit.clone(),
+                    shared: f.shared,
+                })
+                .collect(),
+        })
+        .collect();
+
+    let path = out_dir.join("top_files.json");
+    write_json(&path, &reports)?;
+
+    println!("Largest files per branch saved");
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct LargeObjectReport {
+    blob: String,
+    #[serde(rename = "sizeMB")]
+    size_mb: String,
+    size: u64,
+    paths: Vec<String>,
+    commit: Option<String>,
+    author: Option<String>,
+    branches: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct LargeObjectsReport {
+    #[serde(rename = "minObjectSizeMB")]
+    min_object_size_mb: String,
+    #[serde(rename = "minObjectSize")]
+    min_object_size: u64,
+    objects: Vec<LargeObjectReport>,
+}
+
+pub fn write_large_objects(out_dir: &Path, analysis: &Analysis, min_size: u64, objects: &[LargeObject]) -> Result<()> {
+    let report = LargeObjectsReport {
+        min_object_size_mb: format_size_mb(min_size),
+        min_object_size: min_size,
+        objects: objects
+            .iter()
+            .map(|o| LargeObjectReport {
+                blob: o.blob.clone(),
+                size_mb: format_size_mb(o.size),
+                size: o.size,
+                paths: o.paths.clone(),
+                commit: o.commit.clone(),
+                author: o.author.clone(),
+                branches: o.branches.iter().map(|&b| analysis.branches[b as usize].name.clone()).collect(),
+            })
+            .collect(),
+    };
+
+    let path = out_dir.join("large_objects.json");
+    write_json(&path, &report)?;
+
+    println!("Found {} unmerged blobs of at least {}", objects.len(), format_size_mb(min_size));
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct ReclaimBranch {
+    branch: String,
+    refname: String,
+    #[serde(rename = "marginalSizeMB")]
+    marginal_size_mb: String,
+    #[serde(rename = "marginalSize")]
+    marginal_size: u64,
+    #[serde(flatten)]
+    last_commit: Option<LastCommitReport>,
+}
+
+#[derive(Serialize)]
+struct ReclaimReport {
+    #[serde(rename = "reclaimableSizeMB")]
+    reclaimable_size_mb: String,
+    #[serde(rename = "reclaimableSize")]
+    reclaimable_size: u64,
+    #[serde(rename = "objectCount")]
+    object_count: usize,
+    branches: Vec<ReclaimBranch>,
+}
+
+pub fn write_reclaim_report(out_dir: &Path, analysis: &Analysis, reclaim: &Reclaim) -> Result<()> {
+    let now = analysis.as_of;
+    let report = ReclaimReport {
+        reclaimable_size_mb: format_size_mb(reclaim.size),
+        reclaimable_size: reclaim.size,
+        object_count: reclaim.count,
+        branches: reclaim
+            .marginal
+            .iter()
+            .map(|&(idx, size)| ReclaimBranch {
+                branch: analysis.branches[idx as usize].name.clone(),
+                refname: analysis.branches[idx as usize].refname.clone(),
+                marginal_size_mb: format_size_mb(size),
+                marginal_size: size,
+                last_commit: last_commit(analysis, idx, now),
+            })
+            .collect(),
+    };
+
+    let path = out_dir.join("reclaim.json");
+    write_json(&path, &report)?;
+
+    println!(
+        "Deleting {} branches reclaims {} ({} objects)",
+        reclaim.marginal.len(),
+        format_size_mb(reclaim.size),
+        reclaim.count
+    );
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct PlanStepReport {
+    step: usize,
+    branch: String,
+    refname: String,
+    #[serde(rename = "reclaimedSizeMB")]
+    reclaimed_size_mb: String,
+    #[serde(rename = "reclaimedSize")]
+    reclaimed_size: u64,
+    #[serde(rename = "cumulativeSizeMB")]
+    cumulative_size_mb: String,
+    #[serde(rename = "cumulativeSize")]
+    cumulative_size: u64,
+    #[serde(flatten)]
+    last_commit: Option<LastCommitReport>,
+}
+
+#[derive(Serialize)]
+struct CleanupPlan {
+    #[serde(rename = "targetSize", skip_serializing_if = "Option::is_none")]
+    target_size: Option<u64>,
+    #[serde(rename = "topBranches", skip_serializing_if = "Option::is_none")]
+    top_branches: Option<usize>,
+    #[serde(rename = "targetReached", skip_serializing_if = "Option::is_none")]
+    target_reached: Option<bool>,
+    #[serde(rename = "totalReclaimedSizeMB")]
+    total_reclaimed_size_mb: String,
+    #[serde(rename = "totalReclaimedSize")]
+    total_reclaimed_size: u64,
+    steps: Vec<PlanStepReport>,
+}
+
+pub fn write_cleanup_plan(out_dir: &Path, analysis: &Analysis, goal: PlanGoal, steps: &[PlanStep]) -> Result<()> {
+    let total = steps.last().map(|s| s.cumulative).unwrap_or(0);
+    let now = analysis.as_of;
+    let (target_size, top_branches) = match goal {
+        PlanGoal::TargetBytes(target) => (Some(target), None),
+        PlanGoal::TopK(k) => (None, Some(k)),
+    };
+
+    let plan = CleanupPlan {
+        target_size,
+        top_branches,
+        target_reached: target_size.map(|t| total >= t),
+        total_reclaimed_size_mb: format_size_mb(total),
+        total_reclaimed_size: total,
+        steps: steps
+            .iter()
+            .enumerate()
+            .map(|(i, s)| PlanStepReport {
+                step: i + 1,
+                branch: analysis.branches[s.branch as usize].name.clone(),
+                refname: analysis.branches[s.branch as usize].refname.clone(),
+                reclaimed_size_mb: format_size_mb(s.reclaimed),
+                reclaimed_size: s.reclaimed,
+                cumulative_size_mb: format_size_mb(s.cumulative),
+                cumulative_size: s.cumulative,
+                last_commit: last_commit(analysis, s.branch, now),
+            })
+            .collect(),
+    };
+
+    let path = out_dir.join("cleanup_plan.json");
+    write_json(&path, &plan)?;
+
+    println!("Cleanup plan: delete {} branches to reclaim {}", steps.len(), format_size_mb(total));
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct ErrorReport {
+    branch: String,
+    refname: String,
+    stage: &'static str,
+    reason: String,
+}
+
+// Always written, so an empty array means every branch was read.
+pub fn write_errors(out_dir: &Path, errors: &[BranchError]) -> Result<()> {
+    let reports: Vec<ErrorReport> = errors
+        .iter()
+        .map(|e| ErrorReport {
+            branch: e.branch.clone(),
+            refname: e.refname.clone(),
+            stage: e.stage,
+            reason: e.reason.clone(),
+        })
+        .collect();
+
+    let path = out_dir.join("errors.json");
+    write_json(&path, &reports)?;
+
+    if !errors.is_empty() {
+        eprintln!("{} branch error(s), see {}", errors.len(), path.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_date() {
+        assert_eq!(format_date(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_date(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(format_date(1_700_000_000), "2023-11-14T22:13:20Z");
+        assert_eq!(format_date(-1), "1969-12-31T23:59:59Z");
+    }
+}
@@ -45,6 +45,34 @@ fn test_cli_nonexistent_repo() {
     assert!(!output.status.success());
 }
 
+#[test]
+fn test_exit_codes() {
+    let not_repo = PathBuf::from("/tmp/test-branch-weight-not-a-repo");
+    std::fs::create_dir_all(&not_repo).unwrap();
+    let fixture = create_fixture_repo("/tmp/test-branch-weight-exit-codes");
+
+    let run = |repo: &std::path::Path, extra: &[&str]| {
+        let mut args = vec!["run", "--", "--repo", repo.to_str().unwrap(), "--out", "/tmp/test-branch-weight-exit-out"];
+        args.extend_from_slice(extra);
+        Command::new("cargo")
+            .args(args)
+            .current_dir(get_repo_path())
+            .output()
+            .expect("Failed to run CLI")
+            .status
+            .code()
+    };
+
+    assert_eq!(run(&not_repo, &[]), Some(3));
+    assert_eq!(run(&not_repo, &["--backend", "native"]), Some(3));
+    assert_eq!(run(&fixture, &["--base", "no-such-branch"]), Some(4));
+    assert_eq!(run(&fixture, &["--base", "no-such-branch", "--backend", "native"]), Some(4));
+
+    let _ = std::fs::remove_dir_all("/tmp/test-branch-weight-exit-out");
+    let _ = std::fs::remove_dir_all(&fixture);
+    let _ = std::fs::remove_dir_all(&not_repo);
+}
+
 #[test]
 fn test_output_files_created() {
     let repo = get_repo_path();
@@ -97,3 +125,897 @@ fn test_output_json_valid() {
 
     let _ = std::fs::remove_dir_all(out_dir);
 }
+
+fn git(repo: &std::path::Path, args: &[&str]) {
+    let status = Command::new("git")
+        .args(args)
+        .current_dir(repo)
+        .env("GIT_AUTHOR_NAME", "test")
+        .env("GIT_AUTHOR_EMAIL", "test@example.com")
+        .env("GIT_COMMITTER_NAME", "test")
+        .env("GIT_COMMITTER_EMAIL", "test@example.com")
+        .status()
+        .expect("Failed to run git");
+    assert!(status.success(), "git {:?} failed", args);
+}
+
+fn create_fixture_repo(path: &str) -> PathBuf {
+    let repo = PathBuf::from(path);
+    let _ = std::fs::remove_dir_all(&repo);
+    std::fs::create_dir_all(repo.join("src")).unwrap();
+
+    git(&repo, &["init", "-q", "-b", "master"]);
+    std::fs::write(repo.join("src/lib.rs"), "base\n".repeat(200)).unwrap();
+    git(&repo, &["add", "."]);
+    git(&repo, &["commit", "-q", "-m", "base"]);
+
+    for (name, size) in [("feature/a", 3000), ("feature/b", 5000)] {
+        git(&repo, &["checkout", "-q", "-b", name, "master"]);
+        let payload: String = (0..size).map(|i| char::from(b'a' + (i * 7 % 26) as u8)).collect();
+        std::fs::write(repo.join(format!("{}.txt", name.replace('/', "-"))), payload).unwrap();
+        std::fs::write(repo.join("src/lib.rs"), format!("{}\n", name).repeat(150)).unwrap();
+        git(&repo, &["add", "."]);
+        git(&repo, &["commit", "-q", "-m", name]);
+    }
+
+    git(&repo, &["checkout", "-q", "-b", "feature/c", "feature/a"]);
+    std::fs::write(repo.join("c.txt"), "c\n".repeat(500)).unwrap();
+    git(&repo, &["add", "."]);
+    git(&repo, &["commit", "-q", "-m", "c"]);
+
+    git(&repo, &["checkout", "-q", "master"]);
+    git(&repo, &["repack", "-q", "-a", "-d"]);
+
+    git(&repo, &["checkout", "-q", "-b", "feature/loose", "master"]);
+    std::fs::write(repo.join("loose.txt"), "loose\n".repeat(300)).unwrap();
+    git(&repo, &["add", "."]);
+    git(&repo, &["commit", "-q", "-m", "loose"]);
+    git(&repo, &["checkout", "-q", "master"]);
+
+    repo
+}
+
+#[test]
+fn test_native_backend_matches_git_backend() {
+    let fixture = create_fixture_repo("/tmp/test-branch-weight-fixture");
+    let mut reports = Vec::new();
+
+    for backend in ["git", "native"] {
+        let out_dir = format!("/tmp/test-branch-weight-backend-{}", backend);
+        let _ = std::fs::remove_dir_all(&out_dir);
+
+        let output = Command::new("cargo")
+            .args([
+                "run",
+                "--",
+                "--repo",
+                fixture.to_str().unwrap(),
+                "--out",
+                &out_dir,
+                "--backend",
+                backend,
+                "--delta-info",
+                "--path-breakdown",
+                "--no-cache",
+            ])
+            .current_dir(get_repo_path())
+            .output()
+            .expect("Failed to run CLI");
+
+        assert!(output.status.success(), "CLI failed: {}", String::from_utf8_lossy(&output.stderr));
+
+        let full = std::fs::read_to_string(std::path::Path::new(&out_dir).join("branches_full.json"))
+            .expect("Failed to read branches_full.json");
+        reports.push(serde_json::from_str::<serde_json::Value>(&full).unwrap());
+
+        let _ = std::fs::remove_dir_all(&out_dir);
+    }
+
+    assert_eq!(reports[0].as_array().unwrap().len(), 4);
+    assert!(reports[0][0]["standaloneSize"].is_u64());
+    for branch in reports[0].as_array().unwrap() {
+        let by_extension: u64 = branch["byExtension"].as_array().unwrap().iter().map(|e| e["size"].as_u64().unwrap()).sum();
+        assert_eq!(by_extension, branch["byType"]["blob"]["size"].as_u64().unwrap());
+        assert_eq!(branch["author"], "test");
+        assert_eq!(branch["authorEmail"], "test@example.com");
+        assert!(branch["lastCommitDate"].as_str().unwrap().ends_with('Z'));
+        assert!(branch["ageDays"].is_u64());
+    }
+    let subject = |name: &str| reports[0].as_array().unwrap().iter().find(|b| b["branch"] == name).unwrap()["subject"].clone();
+    assert_eq!(subject("feature/c"), "c");
+    assert_eq!(subject("feature/b"), "feature/b");
+    assert_eq!(reports[0], reports[1]);
+
+    let _ = std::fs::remove_dir_all(&fixture);
+}
+
+#[test]
+fn test_cleanup_plan_written() {
+    let fixture = create_fixture_repo("/tmp/test-branch-weight-plan-fixture");
+    let out_dir = "/tmp/test-branch-weight-plan";
+    let _ = std::fs::remove_dir_all(out_dir);
+
+    let output = Command::new("cargo")
+        .args(["run", "--", "--repo", fixture.to_str().unwrap(), "--out", out_dir, "--plan-target", "100"])
+        .current_dir(get_repo_path())
+        .output()
+        .expect("Failed to run CLI");
+
+    assert!(output.status.success(), "CLI failed: {}", String::from_utf8_lossy(&output.stderr));
+
+    let plan = std::fs::read_to_string(std::path::Path::new(out_dir).join("cleanup_plan.json"))
+        .expect("Failed to read cleanup_plan.json");
+    let plan: serde_json::Value = serde_json::from_str(&plan).unwrap();
+
+    assert_eq!(plan["targetReached"], true);
+    let steps = plan["steps"].as_array().unwrap();
+    assert!(!steps.is_empty());
+    assert_eq!(steps.last().unwrap()["cumulativeSize"], plan["totalReclaimedSize"]);
+
+    // the first step deletes the branch that frees the most on its own
+    let full = std::fs::read_to_string(std::path::Path::new(out_dir).join("branches_full.json")).unwrap();
+    let full: serde_json::Value = serde_json::from_str(&full).unwrap();
+    let marginal: Vec<u64> = full.as_array().unwrap().iter().map(|b| b["marginalSize"].as_u64().unwrap()).collect();
+    let first = full.as_array().unwrap().iter().find(|b| b["refname"] == steps[0]["refname"]).unwrap();
+    assert_eq!(first["marginalSize"], steps[0]["reclaimedSize"]);
+    assert_eq!(marginal.iter().max(), steps[0]["reclaimedSize"].as_u64().as_ref());
+    assert!(full.as_array().unwrap().iter().all(|b| b["marginalSize"] == b["uniqueSize"]));
+
+    let _ = std::fs::remove_dir_all(out_dir);
+    let _ = std::fs::remove_dir_all(&fixture);
+}
+
+#[test]
+fn test_age_filter_and_sort() {
+    let fixture = create_fixture_repo("/tmp/test-branch-weight-age-fixture");
+    git(&fixture, &["checkout", "-q", "feature/b"]);
+    let status = Command::new("git")
+        .args(["-c", "user.name=test", "-c", "user.email=test@example.com", "commit", "-q", "--amend", "--no-edit"])
+        .env("GIT_COMMITTER_DATE", "2020-01-01T00:00:00Z")
+        .current_dir(&fixture)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    git(&fixture, &["checkout", "-q", "master"]);
+
+    let run = |args: &[&str]| {
+        let out_dir = "/tmp/test-branch-weight-age";
+        let _ = std::fs::remove_dir_all(out_dir);
+        let output = Command::new("cargo")
+            .args(["run", "--", "--repo", fixture.to_str().unwrap(), "--out", out_dir, "--no-cache"])
+            .args(args)
+            .current_dir(get_repo_path())
+            .output()
+            .expect("Failed to run CLI");
+        assert!(output.status.success(), "CLI failed: {}", String::from_utf8_lossy(&output.stderr));
+
+        let read = |name: &str| {
+            let json = std::fs::read_to_string(std::path::Path::new(out_dir).join(name)).unwrap();
+            serde_json::from_str::<serde_json::Value>(&json).unwrap()
+        };
+        let reports = (read("branches.json"), read("summary.json"));
+        let _ = std::fs::remove_dir_all(out_dir);
+        reports
+    };
+    let names = |branches: &serde_json::Value| -> Vec<String> {
+        branches.as_array().unwrap().iter().map(|b| b["branch"].as_str().unwrap().to_string()).collect()
+    };
+
+    let (branches, summary) = run(&["--older-than", "1y"]);
+    assert_eq!(names(&branches), ["feature/b"]);
+    assert_eq!(branches[0]["lastCommitDate"], "2020-01-01T00:00:00Z");
+    assert_eq!(summary["skippedBranches"], 3);
+
+    let footprint = summary["totalUnmergedSize"].clone();
+
+    let (branches, unfiltered) = run(&["--sort-by", "age"]);
+    assert_eq!(names(&branches)[0], "feature/b");
+    let (branches, summary) = run(&["--newer-than", "1y", "--sort-by", "score"]);
+    assert!(!names(&branches).contains(&"feature/b".to_string()));
+    assert_eq!(summary["skippedBranches"], 1);
+    // skipped branches, including feature/a which shares objects with
+    // feature/c, still count towards the deduplicated totals
+    assert_eq!(footprint, unfiltered["totalUnmergedSize"]);
+    assert_eq!(summary["deduplicatedSharedSize"], unfiltered["deduplicatedSharedSize"]);
+
+    let _ = std::fs::remove_dir_all(&fixture);
+}
+
+#[test]
+fn test_ref_filters() {
+    let fixture = create_fixture_repo("/tmp/test-branch-weight-filter-fixture");
+    git(&fixture, &["update-ref", "refs/remotes/origin/feature/a", "feature/a"]);
+    git(&fixture, &["update-ref", "refs/remotes/upstream/feature/b", "feature/b"]);
+
+    let branches = |args: &[&str]| -> Vec<String> {
+        let out_dir = "/tmp/test-branch-weight-filter";
+        let _ = std::fs::remove_dir_all(out_dir);
+        let output = Command::new("cargo")
+            .args(["run", "--", "--repo", fixture.to_str().unwrap(), "--out", out_dir, "--no-cache"])
+            .args(args)
+            .current_dir(get_repo_path())
+            .output()
+            .expect("Failed to run CLI");
+        assert!(output.status.success(), "CLI failed: {}", String::from_utf8_lossy(&output.stderr));
+
+        let json = std::fs::read_to_string(std::path::Path::new(out_dir).join("branches.json")).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let _ = std::fs::remove_dir_all(out_dir);
+        let mut names: Vec<String> =
+            json.as_array().unwrap().iter().map(|b| b["branch"].as_str().unwrap().to_string()).collect();
+        names.sort();
+        names
+    };
+
+    assert_eq!(branches(&["--remote", "origin"]), ["origin/feature/a"]);
+    assert_eq!(
+        branches(&["--include", "feature/*", "--exclude", "feature/c", "--exclude", "*/loose"]),
+        ["feature/a", "feature/b"]
+    );
+    assert_eq!(
+        branches(&["--include", "refs/remotes/*", "--exclude", "origin/*"]),
+        ["upstream/feature/b"]
+    );
+
+    let _ = std::fs::remove_dir_all(&fixture);
+}
+
+#[test]
+fn test_bases_instead_of_head() {
+    let fixture = create_fixture_repo("/tmp/test-branch-weight-base-fixture");
+    git(&fixture, &["checkout", "-q", "feature/a"]);
+    let out_dir = "/tmp/test-branch-weight-base";
+
+    let branches = |extra: &[&str]| -> Vec<String> {
+        let _ = std::fs::remove_dir_all(out_dir);
+        let output = Command::new("cargo")
+            .args(["run", "--", "--repo", fixture.to_str().unwrap(), "--out", out_dir])
+            .args(extra)
+            .current_dir(get_repo_path())
+            .output()
+            .expect("Failed to run CLI");
+        assert!(output.status.success(), "CLI failed: {}", String::from_utf8_lossy(&output.stderr));
+
+        let light = std::fs::read_to_string(std::path::Path::new(out_dir).join("branches.json")).unwrap();
+        let light: serde_json::Value = serde_json::from_str(&light).unwrap();
+        let mut names: Vec<String> = light
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|b| b["branch"].as_str().unwrap().to_string())
+            .collect();
+        names.sort();
+        names
+    };
+
+    // HEAD is on feature/a, but the base is master
+    assert_eq!(branches(&[]), vec!["feature/a", "feature/b", "feature/c", "feature/loose"]);
+    assert_eq!(
+        branches(&["--base", "master", "--base", "feature/a*"]),
+        vec!["feature/b", "feature/c", "feature/loose"]
+    );
+
+    let _ = std::fs::remove_dir_all(out_dir);
+    let _ = std::fs::remove_dir_all(&fixture);
+}
+
+#[test]
+fn test_details_for_local_branches() {
+    let fixture = create_fixture_repo("/tmp/test-branch-weight-details-fixture");
+    let out_dir = "/tmp/test-branch-weight-details";
+    let _ = std::fs::remove_dir_all(out_dir);
+
+    let output = Command::new("cargo")
+        .args(["run", "--", "--repo", fixture.to_str().unwrap(), "--out", out_dir, "--details", "10"])
+        .current_dir(get_repo_path())
+        .output()
+        .expect("Failed to run CLI");
+
+    assert!(output.status.success(), "CLI failed: {}", String::from_utf8_lossy(&output.stderr));
+
+    let details = std::fs::read_to_string(std::path::Path::new(out_dir).join("branches_with_commits.json"))
+        .expect("Failed to read branches_with_commits.json");
+    let details: serde_json::Value = serde_json::from_str(&details).unwrap();
+    let details = details.as_array().unwrap();
+
+    let full = std::fs::read_to_string(std::path::Path::new(out_dir).join("branches_full.json")).unwrap();
+    let full: serde_json::Value = serde_json::from_str(&full).unwrap();
+
+    assert_eq!(details.len(), 4);
+    for branch in details {
+        assert_eq!(branch["kind"], "local");
+        assert!(branch["refname"].as_str().unwrap().starts_with("refs/heads/"));
+        let commits = branch["commits"].as_array().unwrap();
+        assert!(!commits.is_empty());
+
+        // commits only count what they introduce, so they add up to the branch
+        let weight = full.as_array().unwrap().iter().find(|b| b["refname"] == branch["refname"]).unwrap();
+        let sum: u64 = commits.iter().map(|c| c["size"].as_u64().unwrap()).sum();
+        assert_eq!(sum, weight["totalSize"].as_u64().unwrap());
+        assert_eq!(branch["totalSize"], weight["totalSize"]);
+    }
+
+    let files = std::fs::read_to_string(std::path::Path::new(out_dir).join("top_files.json"))
+        .expect("Failed to read top_files.json");
+    let files: serde_json::Value = serde_json::from_str(&files).unwrap();
+    let c = files.as_array().unwrap().iter().find(|b| b["branch"] == "feature/c").unwrap();
+    let file = |path: &str| c["files"].as_array().unwrap().iter().find(|f| f["path"] == path).cloned().unwrap();
+    assert_eq!(file("c.txt")["shared"], false);
+    assert_eq!(file("feature-a.txt")["shared"], true);
+    assert_ne!(file("c.txt")["commit"], file("feature-a.txt")["commit"]);
+
+    let _ = std::fs::remove_dir_all(out_dir);
+    let _ = std::fs::remove_dir_all(&fixture);
+}
+
+#[test]
+fn test_unreadable_branch_reported_not_dropped() {
+    let fixture = create_fixture_repo("/tmp/test-branch-weight-errors-fixture");
+    let output = Command::new("git")
+        .args(["rev-parse", "feature/loose:loose.txt"])
+        .current_dir(&fixture)
+        .output()
+        .unwrap();
+    let blob = String::from_utf8(output.stdout).unwrap().trim().to_string();
+    std::fs::remove_file(fixture.join(".git/objects").join(&blob[..2]).join(&blob[2..])).unwrap();
+
+    for backend in ["git", "native"] {
+        let out_dir = format!("/tmp/test-branch-weight-errors-{}", backend);
+        let _ = std::fs::remove_dir_all(&out_dir);
+        let run = |strict: bool| {
+            let mut args = vec!["run", "--", "--repo", fixture.to_str().unwrap(), "--out", &out_dir, "--backend", backend, "--no-cache"];
+            if strict {
+                args.push("--strict");
+            }
+            Command::new("cargo")
+                .args(args)
+                .current_dir(get_repo_path())
+                .output()
+                .expect("Failed to run CLI")
+        };
+
+        let output = run(false);
+        assert!(output.status.success(), "CLI failed: {}", String::from_utf8_lossy(&output.stderr));
+
+        let errors = std::fs::read_to_string(std::path::Path::new(&out_dir).join("errors.json")).unwrap();
+        let errors: serde_json::Value = serde_json::from_str(&errors).unwrap();
+        let errors = errors.as_array().unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0]["refname"], "refs/heads/feature/loose");
+        assert!(errors[0]["reason"].as_str().unwrap().contains(&blob));
+
+        let summary = std::fs::read_to_string(std::path::Path::new(&out_dir).join("summary.json")).unwrap();
+        let summary: serde_json::Value = serde_json::from_str(&summary).unwrap();
+        assert_eq!(summary["failedBranches"], 1);
+        assert_eq!(summary["totalBranches"], 3);
+
+        assert_eq!(run(true).status.code(), Some(8));
+
+        let _ = std::fs::remove_dir_all(&out_dir);
+    }
+
+    let _ = std::fs::remove_dir_all(&fixture);
+}
+
+#[test]
+fn test_unreadable_tip_reported_on_native_backend() {
+    use git_branch_weight::{analyze_branches, AnalysisOptions, NativeGit, RealGit};
+
+    // a branch whose tip is a blob cannot be read as a commit
+    let fixture = create_fixture_repo("/tmp/test-branch-weight-native-broken-fixture");
+    let output = Command::new("git").args(["rev-parse", "feature/c:c.txt"]).current_dir(&fixture).output().unwrap();
+    let blob = String::from_utf8(output.stdout).unwrap().trim().to_string();
+    std::fs::write(fixture.join(".git/refs/heads/not-a-commit"), format!("{}\n", blob)).unwrap();
+
+    let options = AnalysisOptions::new();
+    let native = analyze_branches(&NativeGit::open(&fixture).unwrap(), &fixture, &options).unwrap();
+    let failures = native.failures();
+    assert_eq!(failures.len(), 1);
+    assert_eq!(failures[0].refname, "refs/heads/not-a-commit");
+    assert!(failures[0].reason.contains(&blob));
+
+    // the other branches are analyzed as the git backend analyzes them
+    let real = analyze_branches(&RealGit::open(&fixture).unwrap(), &fixture, &options).unwrap();
+    let weights = |analysis: &git_branch_weight::Analysis| {
+        let mut weights: Vec<_> = analysis.weights.iter().map(|w| (w.branch.clone(), w.unique_size)).collect();
+        weights.sort();
+        weights
+    };
+    assert_eq!(weights(&native).len(), 4);
+    assert_eq!(weights(&native), weights(&real));
+
+    let _ = std::fs::remove_dir_all(&fixture);
+}
+
+#[test]
+fn test_library_api() {
+    use git_branch_weight::{analyze_branch_details, analyze_branches, AnalysisOptions, ObjectKind, RealGit};
+
+    let fixture = create_fixture_repo("/tmp/test-branch-weight-library");
+    let git = RealGit::open(&fixture).unwrap();
+
+    // ages are measured from a fixed point 100 days from now in every report
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs() as i64;
+    let options = AnalysisOptions::new()
+        .base("master")
+        .object_types([ObjectKind::Blob, ObjectKind::Tree])
+        .as_of(now + 100 * 86400);
+    let analysis = analyze_branches(&git, &fixture, &options).unwrap();
+    assert_eq!(analysis.bases, vec!["master".to_string()]);
+    assert_eq!(analysis.weights.len(), 4);
+    assert!(analysis.weights.windows(2).all(|w| w[0].total_size >= w[1].total_size));
+    assert!(analysis.weights.iter().all(|w| w.total_size > w.by_kind.blobs.size));
+
+    let (details, errors) = analyze_branch_details(&git, &fixture, &analysis, 2, 5).unwrap();
+    assert_eq!(details.len(), 2);
+    assert!(errors.is_empty());
+
+    let out_dir = std::path::Path::new("/tmp/test-branch-weight-library-out");
+    let _ = std::fs::remove_dir_all(out_dir);
+    std::fs::create_dir_all(out_dir).unwrap();
+    git_branch_weight::write_reports(out_dir, &analysis).unwrap();
+    git_branch_weight::write_detailed_report(out_dir, &details).unwrap();
+    git_branch_weight::write_top_files(out_dir, &details).unwrap();
+    for name in ["branches_full.json", "branches_with_commits.json", "top_files.json"] {
+        let json = std::fs::read_to_string(out_dir.join(name)).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert!(json.as_array().unwrap().iter().all(|b| b["ageDays"] == 100), "{}", name);
+    }
+
+    let missing = AnalysisOptions::new().base("no-such-branch");
+    assert!(matches!(
+        analyze_branches(&git, &fixture, &missing),
+        Err(git_branch_weight::BranchWeightError::DefaultBranchNotFound(_))
+    ));
+
+    let _ = std::fs::remove_dir_all(out_dir);
+    let _ = std::fs::remove_dir_all(&fixture);
+}
+
+#[test]
+fn test_lfs_pointers_on_both_backends() {
+    use git_branch_weight::{analyze_branches, AnalysisOptions, GitOps, LfsStats, NativeGit, RealGit};
+
+    let fixture = create_fixture_repo("/tmp/test-branch-weight-lfs-fixture");
+    git(&fixture, &["checkout", "-q", "-b", "feature/assets", "master"]);
+    let (fetched, remote) = ("a".repeat(64), "b".repeat(64));
+    for (name, oid, size) in [("logo.psd", &fetched, 40000), ("video.mp4", &remote, 900000)] {
+        let pointer = format!("version https://git-lfs.github.com/spec/v1\noid sha256:{}\nsize {}\n", oid, size);
+        std::fs::write(fixture.join(name), pointer).unwrap();
+    }
+    git(&fixture, &["add", "."]);
+    git(&fixture, &["commit", "-q", "-m", "assets"]);
+    git(&fixture, &["checkout", "-q", "master"]);
+    let store = fixture.join(".git/lfs/objects/aa/aa");
+    std::fs::create_dir_all(&store).unwrap();
+    std::fs::write(store.join(&fetched), vec![1u8; 40000]).unwrap();
+
+    fn lfs_of(git: &impl GitOps, fixture: &std::path::Path) -> Option<LfsStats> {
+        let options = AnalysisOptions::new().lfs(true);
+        let analysis = analyze_branches(git, fixture, &options).unwrap();
+        let assets = analysis.weights.iter().find(|w| w.branch == "feature/assets").unwrap();
+        assert_eq!(assets.lfs, analysis.lfs);
+        assert!(analysis.weights.iter().filter(|w| w.branch != "feature/assets").all(|w| w.lfs.unwrap().count == 0));
+        assets.lfs
+    }
+
+    let expected = LfsStats { size: 940000, local_size: 40000, count: 2, missing_count: 1 };
+    assert_eq!(lfs_of(&RealGit::open(&fixture).unwrap(), &fixture), Some(expected));
+    assert_eq!(lfs_of(&NativeGit::open(&fixture).unwrap(), &fixture), Some(expected));
+
+    let _ = std::fs::remove_dir_all(&fixture);
+}
+
+#[test]
+fn test_group_upstream_on_both_backends() {
+    use git_branch_weight::{analyze_branches, AnalysisOptions, BranchStatus, GitOps, NativeGit, RealGit};
+
+    let fixture = create_fixture_repo("/tmp/test-branch-weight-upstream-fixture");
+    git(&fixture, &["remote", "add", "origin", "/nonexistent"]);
+    git(&fixture, &["config", "branch.feature/a.remote", "origin"]);
+    git(&fixture, &["config", "branch.feature/a.merge", "refs/heads/feature/a"]);
+    // one commit only the remote has, one only the local branch has
+    git(&fixture, &["checkout", "-q", "-b", "pushed", "feature/a"]);
+    std::fs::write(fixture.join("remote.txt"), "remote\n".repeat(400)).unwrap();
+    git(&fixture, &["add", "."]);
+    git(&fixture, &["commit", "-q", "-m", "remote"]);
+    git(&fixture, &["update-ref", "refs/remotes/origin/feature/a", "pushed"]);
+    git(&fixture, &["checkout", "-q", "feature/a"]);
+    git(&fixture, &["branch", "-q", "-D", "pushed"]);
+    std::fs::write(fixture.join("local.txt"), "local\n".repeat(400)).unwrap();
+    git(&fixture, &["add", "."]);
+    git(&fixture, &["commit", "-q", "-m", "local"]);
+    git(&fixture, &["checkout", "-q", "master"]);
+
+    // branch, unique size, shared size and (upstream, ahead, behind)
+    type Row = (String, u64, u64, Option<(String, usize, usize)>);
+    fn grouped(git: &impl GitOps, fixture: &std::path::Path) -> Vec<Row> {
+        let separate = analyze_branches(git, fixture, &AnalysisOptions::new()).unwrap();
+        let analysis = analyze_branches(git, fixture, &AnalysisOptions::new().group_upstreams(true)).unwrap();
+        assert_eq!(analysis.weights.len() + 1, separate.weights.len());
+        let remote = analysis.branches.iter().position(|b| b.name == "origin/feature/a").unwrap();
+        assert_eq!(analysis.statuses[remote], BranchStatus::Skipped("grouped with feature/a".to_string()));
+
+        let local = |weights: &[git_branch_weight::BranchWeight]| {
+            weights.iter().find(|w| w.branch == "feature/a").unwrap().unique_size
+        };
+        assert!(local(&analysis.weights) > local(&separate.weights));
+        // reclaiming the upstream, alone or with its local branch, frees the pair
+        let reclaim = |names: &[&str]| {
+            let indices: Vec<u32> = names.iter().map(|n| analysis.branch_index(n).unwrap()).collect();
+            analysis.reclaimable_if_deleted(&indices).size
+        };
+        assert_eq!(reclaim(&["origin/feature/a"]), local(&analysis.weights));
+        assert_eq!(reclaim(&["feature/a", "origin/feature/a"]), local(&analysis.weights));
+        let mut weights: Vec<_> = analysis
+            .weights
+            .iter()
+            .map(|w| {
+                let upstream = w.upstream.as_ref().map(|u| (u.branch.clone(), u.ahead, u.behind));
+                (w.branch.clone(), w.unique_size, w.shared_size, upstream)
+            })
+            .collect();
+        weights.sort();
+        weights
+    }
+
+    let expected = grouped(&RealGit::open(&fixture).unwrap(), &fixture);
+    assert_eq!(grouped(&NativeGit::open(&fixture).unwrap(), &fixture), expected);
+    let a = expected.iter().find(|w| w.0 == "feature/a").unwrap();
+    assert_eq!(a.3, Some(("origin/feature/a".to_string(), 1, 1)));
+    assert!(expected.iter().filter(|w| w.0 != "feature/a").all(|w| w.3.is_none()));
+
+    let _ = std::fs::remove_dir_all(&fixture);
+}
+
+#[test]
+fn test_details_include_grouped_upstream() {
+    let fixture = create_fixture_repo("/tmp/test-branch-weight-upstream-details-fixture");
+    git(&fixture, &["remote", "add", "origin", "/nonexistent"]);
+    git(&fixture, &["config", "branch.feature/a.remote", "origin"]);
+    git(&fixture, &["config", "branch.feature/a.merge", "refs/heads/feature/a"]);
+    // the remote has moved ahead of the local branch
+    git(&fixture, &["checkout", "-q", "-b", "pushed", "feature/a"]);
+    std::fs::write(fixture.join("remote.txt"), "remote\n".repeat(800)).unwrap();
+    git(&fixture, &["add", "."]);
+    git(&fixture, &["commit", "-q", "-m", "remote"]);
+    git(&fixture, &["update-ref", "refs/remotes/origin/feature/a", "pushed"]);
+    git(&fixture, &["checkout", "-q", "master"]);
+    git(&fixture, &["branch", "-q", "-D", "pushed"]);
+
+    for backend in ["git", "native"] {
+        let out_dir = format!("/tmp/test-branch-weight-upstream-details-{}", backend);
+        let _ = std::fs::remove_dir_all(&out_dir);
+        let output = Command::new("cargo")
+            .args(["run", "--", "--repo", fixture.to_str().unwrap(), "--out", &out_dir, "--no-cache"])
+            .args(["--backend", backend, "--group-upstream", "--details", "10"])
+            .current_dir(get_repo_path())
+            .output()
+            .expect("Failed to run CLI");
+        assert!(output.status.success(), "CLI failed: {}", String::from_utf8_lossy(&output.stderr));
+
+        let read = |name: &str| {
+            let json = std::fs::read_to_string(std::path::Path::new(&out_dir).join(name)).unwrap();
+            serde_json::from_str::<serde_json::Value>(&json).unwrap()
+        };
+        let (full, details) = (read("branches_full.json"), read("branches_with_commits.json"));
+        let a = details.as_array().unwrap().iter().find(|b| b["branch"] == "feature/a").unwrap();
+        let weight = full.as_array().unwrap().iter().find(|b| b["branch"] == "feature/a").unwrap();
+        assert_eq!(weight["upstream"], "origin/feature/a");
+        let sum: u64 = a["commits"].as_array().unwrap().iter().map(|c| c["size"].as_u64().unwrap()).sum();
+        assert_eq!(sum, weight["totalSize"].as_u64().unwrap());
+        assert_eq!(a["totalSize"], weight["totalSize"]);
+        let _ = std::fs::remove_dir_all(&out_dir);
+    }
+
+    let _ = std::fs::remove_dir_all(&fixture);
+}
+
+#[test]
+fn test_shared_clone_on_both_backends() {
+    use git_branch_weight::{analyze_branches, AnalysisOptions, GitOps, NativeGit, RealGit};
+
+    // the clone has no objects of its own, only an alternates file
+    let fixture = create_fixture_repo("/tmp/test-branch-weight-alternates-fixture");
+    let clone = PathBuf::from("/tmp/test-branch-weight-alternates-clone");
+    let _ = std::fs::remove_dir_all(&clone);
+    let status = Command::new("git")
+        .args(["clone", "-q", "--shared", fixture.to_str().unwrap(), clone.to_str().unwrap()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert!(clone.join(".git/objects/info/alternates").exists());
+
+    fn weights(git: &impl GitOps, repo: &std::path::Path) -> Vec<(String, u64, u64)> {
+        let analysis = analyze_branches(git, repo, &AnalysisOptions::new().delta_info(true)).unwrap();
+        assert!(analysis.failures().is_empty());
+        let mut weights: Vec<_> =
+            analysis.weights.iter().map(|w| (w.branch.clone(), w.unique_size, w.shared_size)).collect();
+        weights.sort();
+        weights
+    }
+
+    let expected = weights(&RealGit::open(&clone).unwrap(), &clone);
+    assert_eq!(expected.len(), 4);
+    assert!(expected.iter().all(|(name, _, _)| name.starts_with("origin/")));
+    assert_eq!(weights(&NativeGit::open(&clone).unwrap(), &clone), expected);
+
+    let _ = std::fs::remove_dir_all(&clone);
+    let _ = std::fs::remove_dir_all(&fixture);
+}
+
+#[test]
+fn test_large_objects_on_both_backends() {
+    let fixture = create_fixture_repo("/tmp/test-branch-weight-large-fixture");
+    git(&fixture, &["checkout", "-q", "-b", "feature/dump", "feature/b"]);
+    std::fs::create_dir_all(fixture.join("dumps")).unwrap();
+    // incompressible, so it stays large on disk
+    let mut state = 12345u32;
+    let dump: Vec<u8> = (0..200_000)
+        .map(|_| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            (state >> 16) as u8
+        })
+        .collect();
+    std::fs::write(fixture.join("dumps/db.sql"), dump).unwrap();
+    git(&fixture, &["add", "."]);
+    git(&fixture, &["commit", "-q", "-m", "dump"]);
+    let output = Command::new("git").args(["rev-parse", "feature/dump"]).current_dir(&fixture).output().unwrap();
+    let dump_commit = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    // a commit that only deletes, between the dump and its copy
+    git(&fixture, &["rm", "-q", "feature-b.txt"]);
+    git(&fixture, &["commit", "-q", "-m", "delete"]);
+    // a copy committed "before" the original, as a rebase can leave it
+    std::fs::copy(fixture.join("dumps/db.sql"), fixture.join("dumps/copy.sql")).unwrap();
+    git(&fixture, &["add", "."]);
+    let status = Command::new("git")
+        .args(["-c", "user.name=test", "-c", "user.email=test@example.com", "commit", "-q", "-m", "copy"])
+        .env("GIT_COMMITTER_DATE", "2000-01-01T00:00:00Z")
+        .current_dir(&fixture)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    git(&fixture, &["checkout", "-q", "master"]);
+
+    let mut reports = Vec::new();
+    for backend in ["git", "native"] {
+        let out_dir = format!("/tmp/test-branch-weight-large-{}", backend);
+        let output = Command::new("cargo")
+            .args(["run", "--", "--repo", fixture.to_str().unwrap(), "--out", &out_dir, "--no-cache"])
+            .args(["--backend", backend, "--min-object-size", "100KB"])
+            .current_dir(get_repo_path())
+            .output()
+            .expect("Failed to run CLI");
+        assert!(output.status.success(), "CLI failed: {}", String::from_utf8_lossy(&output.stderr));
+
+        let json = std::fs::read_to_string(std::path::Path::new(&out_dir).join("large_objects.json")).unwrap();
+        reports.push(serde_json::from_str::<serde_json::Value>(&json).unwrap());
+        let _ = std::fs::remove_dir_all(&out_dir);
+    }
+
+    assert_eq!(reports[0], reports[1]);
+    let objects = reports[0]["objects"].as_array().unwrap();
+    assert_eq!(objects.len(), 1);
+    assert_eq!(objects[0]["paths"], serde_json::json!(["dumps/copy.sql", "dumps/db.sql"]));
+    assert_eq!(objects[0]["branches"], serde_json::json!(["feature/dump"]));
+    assert_eq!(objects[0]["commit"], dump_commit.as_str());
+    assert_eq!(objects[0]["author"], "test <test@example.com>");
+    assert!(objects[0]["size"].as_u64().unwrap() >= 100 * 1024);
+
+    let _ = std::fs::remove_dir_all(&fixture);
+}
+
+#[test]
+fn test_cache_reused_between_runs() {
+    let fixture = create_fixture_repo("/tmp/test-branch-weight-cache-fixture");
+    let cache_dir = "/tmp/test-branch-weight-cache";
+    let _ = std::fs::remove_dir_all(cache_dir);
+
+    let run = |out_dir: &str| {
+        let _ = std::fs::remove_dir_all(out_dir);
+        let output = Command::new("cargo")
+            .args(["run", "--", "--repo", fixture.to_str().unwrap(), "--out", out_dir, "--cache-dir", cache_dir])
+            .current_dir(get_repo_path())
+            .output()
+            .expect("Failed to run CLI");
+        assert!(output.status.success(), "CLI failed: {}", String::from_utf8_lossy(&output.stderr));
+
+        let read = |name: &str| {
+            let json = std::fs::read_to_string(std::path::Path::new(out_dir).join(name)).unwrap();
+            serde_json::from_str::<serde_json::Value>(&json).unwrap()
+        };
+        let reports = (read("branches_full.json"), read("summary.json"));
+        let _ = std::fs::remove_dir_all(out_dir);
+        reports
+    };
+
+    let (first, summary) = run("/tmp/test-branch-weight-cache-1");
+    assert_eq!(summary["cacheHits"], 0);
+    assert_eq!(summary["cacheMisses"], 4);
+
+    let (second, summary) = run("/tmp/test-branch-weight-cache-2");
+    assert_eq!(summary["cacheHits"], 4);
+    assert_eq!(summary["cacheMisses"], 0);
+    assert_eq!(first, second);
+
+    // only the moved branch is scanned again
+    git(&fixture, &["checkout", "-q", "feature/b"]);
+    std::fs::write(fixture.join("b2.txt"), "more\n".repeat(400)).unwrap();
+    git(&fixture, &["add", "."]);
+    git(&fixture, &["commit", "-q", "-m", "b2"]);
+    git(&fixture, &["checkout", "-q", "master"]);
+
+    let (third, summary) = run("/tmp/test-branch-weight-cache-3");
+    assert_eq!(summary["cacheHits"], 3);
+    assert_eq!(summary["cacheMisses"], 1);
+    let moved = third.as_array().unwrap().iter().find(|b| b["branch"] == "feature/b").unwrap();
+    let before = first.as_array().unwrap().iter().find(|b| b["branch"] == "feature/b").unwrap();
+    assert!(moved["totalSize"].as_u64() > before["totalSize"].as_u64());
+
+    let _ = std::fs::remove_dir_all(cache_dir);
+    let _ = std::fs::remove_dir_all(&fixture);
+}
+
+// A master commit and a `feature/bench` branch of `commits` commits on top,
+// each changing one of 20 files, written through fast-import.
+fn create_long_branch(path: &str, commits: usize) -> PathBuf {
+    use std::io::Write;
+
+    let repo = PathBuf::from(path);
+    let _ = std::fs::remove_dir_all(&repo);
+    std::fs::create_dir_all(&repo).unwrap();
+    git(&repo, &["init", "-q", "-b", "master"]);
+
+    let mut stream = String::new();
+    let mut commit = |branch: &str, from: Option<&str>, path: &str, content: &str| {
+        stream.push_str(&format!("commit refs/heads/{}\n", branch));
+        stream.push_str("committer test <test@example.com> 1700000000 +0000\ndata 6\nchange\n");
+        if let Some(from) = from {
+            stream.push_str(&format!("from {}\n", from));
+        }
+        stream.push_str(&format!("M 644 inline {}\ndata {}\n{}\n", path, content.len(), content));
+    };
+    commit("master", None, "base.txt", "base");
+    for i in 0..commits {
+        let from = (i == 0).then_some("refs/heads/master");
+        commit("feature/bench", from, &format!("f{}.txt", i % 20), &format!("{}\n", i).repeat(50));
+    }
+
+    let mut fast_import = Command::new("git")
+        .args(["fast-import", "--quiet"])
+        .current_dir(&repo)
+        .stdin(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    fast_import.stdin.take().unwrap().write_all(stream.as_bytes()).unwrap();
+    assert!(fast_import.wait().unwrap().success());
+    repo
+}
+
+// Counts the git processes `--details` starts on a long branch through a
+// logging wrapper: the walk must not spawn any per commit.
+#[test]
+#[cfg(unix)]
+fn test_details_walk_spawns_no_process_per_commit() {
+    use git_branch_weight::{GitOps, RealGit};
+    use std::os::unix::fs::PermissionsExt;
+
+    const COMMITS: usize = 300;
+    let repo = create_long_branch("/tmp/test-branch-weight-details-walk", COMMITS);
+
+    let git_path = Command::new("sh").args(["-c", "command -v git"]).output().unwrap();
+    let git_path = String::from_utf8(git_path.stdout).unwrap().trim().to_string();
+    let bin = PathBuf::from("/tmp/test-branch-weight-details-walk-bin");
+    let log = bin.join("calls.log");
+    let _ = std::fs::remove_dir_all(&bin);
+    std::fs::create_dir_all(&bin).unwrap();
+    let wrapper = format!("#!/bin/sh\necho \"$*\" >> {}\nexec {} \"$@\"\n", log.display(), git_path);
+    std::fs::write(bin.join("git"), wrapper).unwrap();
+    std::fs::set_permissions(bin.join("git"), std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    let out_dir = "/tmp/test-branch-weight-details-walk-out";
+    let path = format!("{}:{}", bin.display(), std::env::var("PATH").unwrap_or_default());
+    let output = Command::new(env!("CARGO_BIN_EXE_git-branch-weight"))
+        .args(["--repo", repo.to_str().unwrap(), "--out", out_dir, "--no-cache", "-y", "--details", "1"])
+        .env("PATH", path)
+        .output()
+        .expect("Failed to run CLI");
+    assert!(output.status.success(), "CLI failed: {}", String::from_utf8_lossy(&output.stderr));
+
+    let calls = std::fs::read_to_string(&log).unwrap();
+    let count = |command: &str| calls.lines().filter(|line| line.split(' ').any(|word| word == command)).count();
+    assert_eq!(count("diff-tree"), 1, "{}", calls);
+    assert!(calls.lines().count() < 30, "{} git processes for {} commits", calls.lines().count(), COMMITS);
+
+    let git = RealGit::open(&repo).unwrap();
+    let commits = git.get_unmerged_commits(&repo, "feature/bench", &["master".to_string()]).unwrap();
+    assert_eq!(commits.len(), COMMITS);
+    assert!(commits.iter().all(|c| c.blobs.len() == 1));
+
+    let _ = std::fs::remove_dir_all(out_dir);
+    let _ = std::fs::remove_dir_all(&bin);
+    let _ = std::fs::remove_dir_all(&repo);
+}
+
+// Times the details walk of a 2,000-commit branch both ways: one
+// `diff-tree` per commit, as `--details` used to run, and the single
+// streamed `diff-tree --stdin` it runs now. Run it with
+// `cargo test --release --test integration_test -- --ignored --nocapture bench_details_walk`.
+#[test]
+#[ignore]
+fn bench_details_walk() {
+    use git_branch_weight::{GitOps, RealGit};
+    use std::collections::{BTreeMap, HashMap};
+    use std::io::Write;
+    use std::time::Instant;
+
+    const COMMITS: usize = 2000;
+    let repo = create_long_branch("/tmp/test-branch-weight-bench-walk", COMMITS);
+    let exclude = ["master".to_string()];
+
+    // the per-commit path sizes its blobs in one batch-check at the end, so
+    // the two only differ in how commits are diffed
+    let started = Instant::now();
+    let listed = Command::new("git")
+        .args(["rev-list", "feature/bench", "--not", "master"])
+        .current_dir(&repo)
+        .output()
+        .unwrap();
+    let mut changed = Vec::new();
+    for commit in String::from_utf8(listed.stdout).unwrap().lines() {
+        let diff = Command::new("git")
+            .args(["diff-tree", "-r", "--diff-filter=AM", "--no-commit-id", commit])
+            .current_dir(&repo)
+            .output()
+            .unwrap();
+        let blobs: Vec<String> = String::from_utf8(diff.stdout)
+            .unwrap()
+            .lines()
+            .filter_map(|line| line.split_whitespace().nth(3).map(str::to_string))
+            .collect();
+        changed.push((commit.to_string(), blobs));
+    }
+    let mut batch_check = Command::new("git")
+        .args(["cat-file", "--batch-check=%(objectname) %(objectsize:disk)"])
+        .current_dir(&repo)
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    let request: String = changed.iter().flat_map(|(_, blobs)| blobs).map(|blob| format!("{}\n", blob)).collect();
+    batch_check.stdin.take().unwrap().write_all(request.as_bytes()).unwrap();
+    let sizes = String::from_utf8(batch_check.wait_with_output().unwrap().stdout).unwrap();
+    let sizes: HashMap<&str, u64> = sizes
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .map(|(oid, size)| (oid, size.parse().unwrap()))
+        .collect();
+    let per_commit: BTreeMap<String, HashMap<String, u64>> = changed
+        .into_iter()
+        .map(|(commit, blobs)| (commit, blobs.into_iter().map(|blob| (blob.clone(), sizes[blob.as_str()])).collect()))
+        .collect();
+    let per_commit_time = started.elapsed();
+
+    let started = Instant::now();
+    let git = RealGit::open(&repo).unwrap();
+    let streamed: BTreeMap<_, _> = git
+        .get_unmerged_commits(&repo, "feature/bench", &exclude)
+        .unwrap()
+        .into_iter()
+        .map(|c| (c.commit, c.blobs))
+        .collect();
+    let streamed_time = started.elapsed();
+
+    assert_eq!(streamed.len(), COMMITS);
+    assert_eq!(streamed, per_commit);
+    println!(
+        "details walk of {} commits: per commit {:.2?}, streamed {:.2?} ({:.0}x)",
+        COMMITS,
+        per_commit_time,
+        streamed_time,
+        per_commit_time.as_secs_f64() / streamed_time.as_secs_f64()
+    );
+    assert!(streamed_time < per_commit_time);
+
+    let _ = std::fs::remove_dir_all(&repo);
+}